@@ -0,0 +1,542 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use todo_events::TodoAction;
+
+use crate::filter::Filter;
+
+/// Digests are held unacked, so they must fit in the consumer's default
+/// limit of 1000 pending acks.
+pub const MAX_DIGEST_EVENTS: usize = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("environment variable {0} referenced by the config is not set")]
+    MissingEnv(String),
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+/// Broadcaster configuration file. Every event is sent to every sink; each
+/// sink has its own durable consumer, so a failing sink is retried and
+/// dead-lettered without resending to the others.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Templates file for sinks that do not name their own; see
+    /// [`crate::templates`]. Falls back to `BROADCASTER_TEMPLATES`, then to
+    /// the built-in messages.
+    pub templates: Option<PathBuf>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    /// Identifies the sink in logs and in its consumer name. Defaults to the
+    /// sink type.
+    pub name: Option<String>,
+    /// Templates file for this sink only.
+    pub templates: Option<PathBuf>,
+    /// Sends digests instead of one message per event.
+    pub digest: Option<DigestConfig>,
+    /// Limits which events the sink receives.
+    pub filter: Option<FilterConfig>,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+}
+
+/// Digest mode: events are held back and sent as one message once
+/// `max_events` have arrived or `window_secs` have passed since the first.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DigestConfig {
+    pub window_secs: u64,
+    #[serde(default = "default_digest_max_events")]
+    pub max_events: usize,
+    /// Actions still sent on their own, right away.
+    #[serde(default)]
+    pub immediate: Vec<TodoAction>,
+}
+
+impl DigestConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+}
+
+/// Rules an event must pass to be sent to the sink; see [`crate::filter`].
+/// Filtered events are acked without being sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Actions to send; all of them when empty.
+    #[serde(default)]
+    pub actions: Vec<TodoAction>,
+    /// Regex the todo's content must match, e.g. `(?i)urgent`.
+    pub content: Option<String>,
+    pub quiet_hours: Option<QuietHoursConfig>,
+}
+
+/// Nothing is sent between `start` and `end` (`HH:MM`, wrapping past
+/// midnight when `end` is earlier) in `timezone`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHoursConfig {
+    pub start: String,
+    pub end: String,
+    /// IANA name such as `Europe/Helsinki`.
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+/// Rejects unknown keys itself, since `SinkConfig` flattens it and so cannot.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkKind {
+    Telegram(TelegramConfig),
+    /// POSTs the event JSON, signed with HMAC-SHA256 when `secret` is set.
+    Webhook {
+        url: String,
+        secret: Option<String>,
+    },
+    /// Slack incoming webhook.
+    Slack {
+        url: String,
+    },
+    /// Discord webhook.
+    Discord {
+        url: String,
+    },
+    Smtp(SmtpConfig),
+    /// Takes no settings; a struct variant so that any are rejected.
+    Stdout {},
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+    #[serde(default = "default_telegram_api_url")]
+    pub api_url: String,
+    /// Attempts per delivery before the consumer's own redelivery takes over.
+    #[serde(default = "default_telegram_max_attempts")]
+    pub max_attempts: u32,
+    /// Telegram allows about 20 messages a minute in a group chat.
+    #[serde(default = "default_telegram_rate_limit_per_minute")]
+    pub rate_limit_per_minute: u32,
+    #[serde(default = "default_telegram_burst")]
+    pub burst: u32,
+}
+
+impl TelegramConfig {
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self {
+            bot_token,
+            chat_id,
+            api_url: default_telegram_api_url(),
+            max_attempts: default_telegram_max_attempts(),
+            rate_limit_per_minute: default_telegram_rate_limit_per_minute(),
+            burst: default_telegram_burst(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    Tls,
+    /// Plain text, for local relays and tests only.
+    None,
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_digest_max_events() -> usize {
+    50
+}
+
+fn default_telegram_max_attempts() -> u32 {
+    4
+}
+
+fn default_telegram_rate_limit_per_minute() -> u32 {
+    20
+}
+
+fn default_telegram_burst() -> u32 {
+    3
+}
+
+impl SinkConfig {
+    pub fn name(&self) -> &str {
+        match &self.name {
+            Some(name) => name,
+            None => match self.kind {
+                SinkKind::Telegram(_) => "telegram",
+                SinkKind::Webhook { .. } => "webhook",
+                SinkKind::Slack { .. } => "slack",
+                SinkKind::Discord { .. } => "discord",
+                SinkKind::Smtp(_) => "smtp",
+                SinkKind::Stdout {} => "stdout",
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Path of the templates file shared by sinks without their own.
+    pub fn templates_path(&self) -> Option<PathBuf> {
+        self.templates
+            .clone()
+            .or_else(|| std::env::var_os("BROADCASTER_TEMPLATES").map(PathBuf::from))
+    }
+
+    /// Loads the file named by `BROADCASTER_CONFIG`. Without it, a single
+    /// sink is derived from the older environment variables: stdout when
+    /// `STAGING=true`, otherwise Telegram from `TELEGRAM_BOT_TOKEN` and
+    /// `TELEGRAM_CHAT_ID`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        if let Ok(path) = std::env::var("BROADCASTER_CONFIG") {
+            return Self::load(path);
+        }
+
+        let is_staging = std::env::var("STAGING").unwrap_or_else(|_| "false".to_string()) == "true";
+
+        let kind = if is_staging {
+            SinkKind::Stdout {}
+        } else {
+            let env = |name: &str| std::env::var(name).map_err(|_| ConfigError::MissingEnv(name.to_string()));
+            SinkKind::Telegram(TelegramConfig::new(env("TELEGRAM_BOT_TOKEN")?, env("TELEGRAM_CHAT_ID")?))
+        };
+
+        Ok(Self {
+            templates: None,
+            sinks: vec![SinkConfig {
+                name: None,
+                templates: None,
+                digest: None,
+                filter: None,
+                kind,
+            }],
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        Self::parse(&text)
+    }
+
+    /// Parses a TOML config, substituting `${VAR}` in string values with
+    /// environment variables so secrets can stay out of the file.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        Self::parse_with_env(text, |name| std::env::var(name).ok())
+    }
+
+    /// Expands after parsing, so a value holding quotes, backslashes or
+    /// newlines cannot break the TOML around it.
+    fn parse_with_env(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut value: toml::Value = toml::from_str(text)?;
+        expand_values(&mut value, &lookup)?;
+
+        let config: Self = value.try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.sinks.is_empty() {
+            return Err(ConfigError::Invalid("at least one sink is required".to_string()));
+        }
+
+        let mut names = HashSet::new();
+
+        for sink in &self.sinks {
+            let name = sink.name();
+
+            let valid_name = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(ConfigError::Invalid(format!(
+                    "sink name '{}' may only contain letters, digits, '-' and '_'",
+                    name
+                )));
+            }
+
+            if !names.insert(name) {
+                return Err(ConfigError::Invalid(format!("duplicate sink name '{}'", name)));
+            }
+
+            match &sink.kind {
+                SinkKind::Smtp(smtp) if smtp.to.is_empty() => {
+                    return Err(ConfigError::Invalid(format!("sink '{}' has no recipients", name)));
+                }
+                SinkKind::Telegram(telegram) if telegram.max_attempts == 0 || telegram.rate_limit_per_minute == 0 => {
+                    return Err(ConfigError::Invalid(format!(
+                        "sink '{}' needs max_attempts and rate_limit_per_minute of at least 1",
+                        name
+                    )));
+                }
+                _ => {}
+            }
+
+            if let Some(digest) = &sink.digest {
+                if digest.window_secs == 0 || !(1..=MAX_DIGEST_EVENTS).contains(&digest.max_events) {
+                    return Err(ConfigError::Invalid(format!(
+                        "sink '{}' needs a digest window of at least 1s and 1 to {} max_events",
+                        name, MAX_DIGEST_EVENTS
+                    )));
+                }
+                if digest.immediate.contains(&TodoAction::Unknown) {
+                    return Err(ConfigError::Invalid(format!(
+                        "sink '{}' lists an unknown action under digest.immediate",
+                        name
+                    )));
+                }
+            }
+
+            if let Some(filter) = &sink.filter {
+                match Filter::from_config(filter) {
+                    Ok(_) => {}
+                    Err(ConfigError::Invalid(reason)) => {
+                        return Err(ConfigError::Invalid(format!("sink '{}': {}", name, reason)));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn expand_values(value: &mut toml::Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+    match value {
+        toml::Value::String(text) => *text = expand_env(text, lookup)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_values(value, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_values(value, lookup)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn expand_env(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| ConfigError::Invalid("unterminated ${ in config".to_string()))?;
+        let name = &after[..end];
+
+        expanded.push_str(&lookup(name).ok_or_else(|| ConfigError::MissingEnv(name.to_string()))?);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sinks_reject_unknown_keys() {
+        for sink in [
+            "type = \"webhook\"\nurl = \"https://example.com/hook\"\nsecert = \"s3cret\"",
+            "type = \"slack\"\nurl = \"https://hooks.slack.com/x\"\nchannel = \"#todos\"",
+            "type = \"discord\"\nurl = \"https://discord.com/api/webhooks/x\"\nusername = \"todos\"",
+            "type = \"telegram\"\nbot_token = \"123:abc\"\nchat_id = \"42\"\nrate_limit = 5",
+            "type = \"smtp\"\nhost = \"mail\"\nfrom = \"a@example.com\"\nto = []\nstarttls = true",
+            "type = \"stdout\"\nformat = \"json\"",
+            "type = \"stdout\"\ntemplate = \"t.toml\"",
+        ] {
+            let result = Config::parse(&format!("[[sinks]]\n{}\n", sink));
+            assert!(matches!(result, Err(ConfigError::Parse(_))), "accepted {}: {:?}", sink, result);
+        }
+    }
+
+    #[test]
+    fn parses_every_sink_type() {
+        let config = Config::parse(
+            r#"
+            [[sinks]]
+            type = "telegram"
+            bot_token = "123:abc"
+            chat_id = "42"
+
+            [[sinks]]
+            name = "audit-hook"
+            type = "webhook"
+            url = "https://example.com/hook"
+            secret = "s3cret"
+
+            [[sinks]]
+            type = "slack"
+            url = "https://hooks.slack.com/services/x"
+
+            [[sinks]]
+            type = "discord"
+            url = "https://discord.com/api/webhooks/x"
+
+            [[sinks]]
+            type = "smtp"
+            host = "mail.example.com"
+            port = 2525
+            from = "Todos <todos@example.com>"
+            to = ["team@example.com"]
+
+            [sinks.filter]
+            actions = ["created"]
+            content = "(?i)urgent"
+
+            [sinks.filter.quiet_hours]
+            start = "22:00"
+            end = "07:00"
+
+            [[sinks]]
+            type = "stdout"
+
+            [sinks.digest]
+            window_secs = 300
+            immediate = ["deleted"]
+            "#,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = config.sinks.iter().map(SinkConfig::name).collect();
+        assert_eq!(names, ["telegram", "audit-hook", "slack", "discord", "smtp", "stdout"]);
+
+        match &config.sinks[0].kind {
+            SinkKind::Telegram(telegram) => {
+                assert_eq!(telegram.max_attempts, 4);
+                assert_eq!(telegram.rate_limit_per_minute, 20);
+            }
+            other => panic!("expected telegram sink, got {:?}", other),
+        }
+
+        assert_eq!(
+            config.sinks[5].digest,
+            Some(DigestConfig {
+                window_secs: 300,
+                max_events: 50,
+                immediate: vec![TodoAction::Deleted],
+            })
+        );
+        assert_eq!(config.sinks[0].digest, None);
+
+        assert_eq!(
+            config.sinks[4].filter,
+            Some(FilterConfig {
+                actions: vec![TodoAction::Created],
+                content: Some("(?i)urgent".to_string()),
+                quiet_hours: Some(QuietHoursConfig {
+                    start: "22:00".to_string(),
+                    end: "07:00".to_string(),
+                    timezone: "UTC".to_string(),
+                }),
+            })
+        );
+
+        match &config.sinks[4].kind {
+            SinkKind::Smtp(smtp) => {
+                assert_eq!(smtp.port, Some(2525));
+                assert_eq!(smtp.tls, SmtpTls::Starttls);
+            }
+            other => panic!("expected smtp sink, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let duplicate = "[[sinks]]\ntype = \"stdout\"\n[[sinks]]\ntype = \"stdout\"\n";
+
+        assert!(matches!(Config::parse(duplicate), Err(ConfigError::Invalid(_))));
+        assert!(matches!(Config::parse("sinks = []"), Err(ConfigError::Invalid(_))));
+
+        let unknown_immediate = "[[sinks]]\ntype = \"stdout\"\n[sinks.digest]\nwindow_secs = 60\nimmediate = [\"archived\"]\n";
+        assert!(matches!(Config::parse(unknown_immediate), Err(ConfigError::Invalid(_))));
+
+        let bad_regex = "[[sinks]]\ntype = \"stdout\"\n[sinks.filter]\ncontent = \"[\"\n";
+        assert!(matches!(Config::parse(bad_regex), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        let lookup = |name: &str| (name == "TOKEN").then(|| "123:abc".to_string());
+
+        assert_eq!(expand_env("token = \"${TOKEN}\"", lookup).unwrap(), "token = \"123:abc\"");
+        assert!(matches!(expand_env("${MISSING}", lookup), Err(ConfigError::MissingEnv(name)) if name == "MISSING"));
+    }
+
+    #[test]
+    fn expanded_values_are_not_parsed_as_toml() {
+        let secret = "a\"b\\c\n[[sinks]]\ntype = \"stdout\"";
+        let lookup = |name: &str| (name == "SECRET").then(|| secret.to_string());
+
+        let config = Config::parse_with_env(
+            "[[sinks]]\ntype = \"webhook\"\nurl = \"https://example.com/${SECRET}\"\nsecret = \"${SECRET}\"\n",
+            lookup,
+        )
+        .unwrap();
+
+        assert_eq!(config.sinks.len(), 1);
+        match &config.sinks[0].kind {
+            SinkKind::Webhook { url, secret: Some(value) } => {
+                assert_eq!(url, &format!("https://example.com/{}", secret));
+                assert_eq!(value, secret);
+            }
+            other => panic!("expected webhook sink, got {:?}", other),
+        }
+    }
+}
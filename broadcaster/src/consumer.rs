@@ -0,0 +1,604 @@
+use std::future::Future;
+use std::time::Duration;
+
+use async_nats::jetstream::consumer::{pull, AckPolicy, DeliverPolicy, PullConsumer};
+use async_nats::jetstream::{self, AckKind, Message};
+use chrono::Utc;
+use futures::stream::StreamExt;
+use todo_events::TodoEvent;
+use tokio::time::Instant;
+
+use crate::config::DigestConfig;
+use crate::filter::Filter;
+use crate::lease;
+use crate::metrics::SinkMetrics;
+use crate::shutdown::Shutdown;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Headers set on messages moved to [`todo_events::DEAD_LETTER_SUBJECT`].
+pub const DEAD_LETTER_REASON_HEADER: &str = "Todo-Dead-Letter-Reason";
+pub const DELIVERY_COUNT_HEADER: &str = "Todo-Delivery-Count";
+pub const STREAM_SEQUENCE_HEADER: &str = "Todo-Stream-Sequence";
+
+const MAX_REDELIVERY_DELAY: Duration = Duration::from_secs(60);
+/// How long a pull request waits on the server for a message.
+const FETCH_EXPIRY: Duration = Duration::from_secs(30);
+const MIN_FETCH_EXPIRY: Duration = Duration::from_secs(1);
+const FETCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Why a handler could not deliver an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub reason: String,
+    /// Redeliver no sooner than this, when the sink said so.
+    pub retry_after: Option<Duration>,
+}
+
+impl From<String> for Failure {
+    fn from(reason: String) -> Self {
+        Self { reason, retry_after: None }
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(reason: &str) -> Self {
+        reason.to_string().into()
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+/// Settings of the durable pull consumer shared by all broadcaster replicas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerConfig {
+    /// Name of the durable consumer; the binary appends each sink's name.
+    pub durable_name: String,
+    /// Deliveries before a message that keeps failing is dead-lettered.
+    pub max_deliver: i64,
+    /// How long the server waits for an ack before redelivering; a send to
+    /// Telegram has to finish within it.
+    pub ack_wait: Duration,
+    /// How long in-flight sends may take after a shutdown signal before
+    /// their messages are handed back for redelivery. Also bounds how long a
+    /// replica that lost a [lease](crate::lease) keeps sending, so it must
+    /// stay below `LEASE_TTL - RENEW_INTERVAL`.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            durable_name: "broadcaster".to_string(),
+            max_deliver: 5,
+            ack_wait: Duration::from_secs(30),
+            shutdown_timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+impl ConsumerConfig {
+    /// Reads `BROADCASTER_DURABLE_NAME`, `BROADCASTER_MAX_DELIVER`,
+    /// `BROADCASTER_ACK_WAIT_SECS` and `BROADCASTER_SHUTDOWN_TIMEOUT_SECS`,
+    /// falling back to the defaults.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = Self::default();
+
+        if let Ok(name) = std::env::var("BROADCASTER_DURABLE_NAME") {
+            config.durable_name = name;
+        }
+
+        if let Ok(value) = std::env::var("BROADCASTER_MAX_DELIVER") {
+            config.max_deliver = value
+                .parse()
+                .ok()
+                .filter(|max_deliver| *max_deliver >= 1)
+                .ok_or("BROADCASTER_MAX_DELIVER must be a positive integer")?;
+        }
+
+        if let Ok(value) = std::env::var("BROADCASTER_ACK_WAIT_SECS") {
+            let secs: u64 = value
+                .parse()
+                .ok()
+                .filter(|secs| *secs >= 1)
+                .ok_or("BROADCASTER_ACK_WAIT_SECS must be a positive integer")?;
+            config.ack_wait = Duration::from_secs(secs);
+        }
+
+        if let Ok(value) = std::env::var("BROADCASTER_SHUTDOWN_TIMEOUT_SECS") {
+            let secs: u64 = value
+                .parse()
+                .map_err(|_| "BROADCASTER_SHUTDOWN_TIMEOUT_SECS must be a non-negative integer")?;
+            config.shutdown_timeout = Duration::from_secs(secs);
+
+            if config.shutdown_timeout >= lease::LEASE_TTL - lease::RENEW_INTERVAL {
+                return Err(format!(
+                    "BROADCASTER_SHUTDOWN_TIMEOUT_SECS must be below {}",
+                    (lease::LEASE_TTL - lease::RENEW_INTERVAL).as_secs()
+                )
+                .into());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Creates the `todo.events` and dead-letter streams if needed, then creates
+/// or updates the durable consumer so configuration changes take effect on
+/// the next start.
+pub async fn create_consumer(jetstream: &jetstream::Context, config: &ConsumerConfig) -> Result<PullConsumer, Error> {
+    let stream = todo_events::jetstream::get_or_create_stream(jetstream).await?;
+    todo_events::jetstream::get_or_create_dead_letter_stream(jetstream).await?;
+
+    let consumer = stream
+        .create_consumer(pull::Config {
+            durable_name: Some(config.durable_name.clone()),
+            filter_subject: todo_events::SUBJECT.to_string(),
+            // Only applies when the consumer is first created, so a fresh
+            // deployment does not replay the whole stream to Telegram.
+            deliver_policy: DeliverPolicy::New,
+            ack_policy: AckPolicy::Explicit,
+            ack_wait: config.ack_wait,
+            max_deliver: config.max_deliver,
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(consumer)
+}
+
+/// Feeds every event that passes `filter` to `handler`, one at a time.
+/// Filtered events are acked without being sent. Successes are acked,
+/// failures are redelivered with a growing delay (or after the sink's
+/// `retry_after`), and events that failed `max_deliver` times or cannot be
+/// decoded go to the dead-letter subject.
+///
+/// Messages are pulled singly rather than prefetched, so an event waiting
+/// behind a slow or rate-limited send does not hit its ack wait.
+///
+/// Returns once `shutdown` is requested: no more messages are pulled and the
+/// send in flight gets `config.shutdown_timeout` to finish; if it does not,
+/// its message is nacked so another replica picks it up straight away.
+pub async fn run<F, Fut>(
+    jetstream: &jetstream::Context,
+    consumer: &PullConsumer,
+    config: &ConsumerConfig,
+    metrics: &SinkMetrics,
+    shutdown: &Shutdown,
+    filter: &Filter,
+    handler: F,
+) -> Result<(), Error>
+where
+    F: Fn(TodoEvent) -> Fut,
+    Fut: Future<Output = Result<(), Failure>>,
+{
+    tracing::info!(
+        "Consuming {} with durable consumer '{}'",
+        todo_events::SUBJECT,
+        config.durable_name
+    );
+
+    loop {
+        let message = tokio::select! {
+            biased;
+            _ = shutdown.requested() => break,
+            message = next_message(consumer, FETCH_EXPIRY) => message,
+        };
+        let Some(message) = message else {
+            continue;
+        };
+
+        let handling = handle(jetstream, config, metrics, filter, &message, &[], &handler);
+        match shutdown.within_grace(config.shutdown_timeout, handling).await {
+            Some(Ok(())) => {}
+            Some(Err(e)) => tracing::error!("Failed to settle message: {}", e),
+            None => {
+                abandon(&[&message]).await;
+                break;
+            }
+        }
+    }
+
+    tracing::info!("Stopped consuming with durable consumer '{}'", config.durable_name);
+
+    Ok(())
+}
+
+/// Like [`run`], but collects events and hands them to `send_digest` once
+/// `digest.max_events` have arrived or `digest.window()` has passed since the
+/// first. Actions in `digest.immediate` go to `send_one` right away. Events
+/// are filtered as they arrive, so filtered ones never hold a digest open or
+/// take up room in it. Collected
+/// messages stay unacked until their digest is sent, so a crash resends them
+/// instead of losing them.
+///
+/// On shutdown the collected events are sent as a final digest, under the
+/// same deadline as [`run`].
+#[allow(clippy::too_many_arguments)]
+pub async fn run_digest<F, FFut, G, GFut>(
+    jetstream: &jetstream::Context,
+    consumer: &PullConsumer,
+    config: &ConsumerConfig,
+    metrics: &SinkMetrics,
+    shutdown: &Shutdown,
+    filter: &Filter,
+    digest: &DigestConfig,
+    send_one: F,
+    send_digest: G,
+) -> Result<(), Error>
+where
+    F: Fn(TodoEvent) -> FFut,
+    FFut: Future<Output = Result<(), Failure>>,
+    G: Fn(Vec<TodoEvent>) -> GFut,
+    GFut: Future<Output = Result<(), Failure>>,
+{
+    tracing::info!(
+        "Consuming {} with durable consumer '{}' in digest mode ({}s window, up to {} events)",
+        todo_events::SUBJECT,
+        config.durable_name,
+        digest.window_secs,
+        digest.max_events
+    );
+
+    let progress_period = config.ack_wait / 2;
+    let mut pending: Vec<Pending> = Vec::new();
+    let mut flush_at: Option<Instant> = None;
+    let mut progress_at = Instant::now() + progress_period;
+
+    loop {
+        let now = Instant::now();
+
+        if shutdown.is_requested() || pending.len() >= digest.max_events || flush_at.is_some_and(|at| at <= now) {
+            let batch = std::mem::take(&mut pending);
+            flush_at = None;
+
+            if !batch.is_empty() {
+                let flushing = flush_digest(jetstream, config, metrics, &batch, &send_digest);
+                if shutdown.within_grace(config.shutdown_timeout, flushing).await.is_none() {
+                    abandon(&batch.iter().map(|held| &held.message).collect::<Vec<_>>()).await;
+                    break;
+                }
+            }
+
+            if shutdown.is_requested() {
+                break;
+            }
+            continue;
+        }
+
+        if progress_at <= now {
+            for held in &pending {
+                if let Err(e) = held.message.ack_with(AckKind::Progress).await {
+                    tracing::warn!("Failed to extend ack wait: {}", e);
+                }
+            }
+            progress_at = now + progress_period;
+        }
+
+        let mut wait = FETCH_EXPIRY;
+        if let Some(at) = flush_at {
+            wait = wait.min(at - now);
+        }
+        if !pending.is_empty() {
+            wait = wait.min(progress_at - now);
+        }
+
+        let message = tokio::select! {
+            biased;
+            _ = shutdown.requested() => continue,
+            message = next_message(consumer, wait.max(MIN_FETCH_EXPIRY)) => message,
+        };
+        let Some(message) = message else {
+            continue;
+        };
+
+        let (event, delivered) = match decode(jetstream, metrics, &message).await {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::error!("Failed to settle message: {}", e);
+                continue;
+            }
+        };
+
+        match skip_filtered(metrics, filter, &message, &event).await {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                tracing::error!("Failed to settle message: {}", e);
+                continue;
+            }
+        }
+
+        if digest.immediate.contains(&event.action) {
+            let in_flight: Vec<&Message> = std::iter::once(&message)
+                .chain(pending.iter().map(|held| &held.message))
+                .collect();
+            let event_id = event.event_id;
+            let started = Instant::now();
+            let sending = with_progress(config, &in_flight, send_one(event));
+            let Some(result) = shutdown.within_grace(config.shutdown_timeout, sending).await else {
+                abandon(&in_flight).await;
+                break;
+            };
+            metrics.record_delivery(1, result.is_ok(), started.elapsed());
+
+            if let Err(e) = settle(jetstream, config, metrics, &message, delivered, &event_id.to_string(), &result).await {
+                tracing::error!("Failed to settle message: {}", e);
+            }
+            continue;
+        }
+
+        flush_at.get_or_insert_with(|| Instant::now() + digest.window());
+        pending.push(Pending { message, event, delivered });
+    }
+
+    tracing::info!("Stopped consuming with durable consumer '{}'", config.durable_name);
+
+    Ok(())
+}
+
+/// A message collected for the next digest.
+struct Pending {
+    message: Message,
+    event: TodoEvent,
+    delivered: i64,
+}
+
+async fn flush_digest<G, GFut>(
+    jetstream: &jetstream::Context,
+    config: &ConsumerConfig,
+    metrics: &SinkMetrics,
+    batch: &[Pending],
+    send_digest: &G,
+) where
+    G: Fn(Vec<TodoEvent>) -> GFut,
+    GFut: Future<Output = Result<(), Failure>>,
+{
+    let messages: Vec<&Message> = batch.iter().map(|held| &held.message).collect();
+    let events = batch.iter().map(|held| held.event.clone()).collect();
+
+    let started = Instant::now();
+    let result = with_progress(config, &messages, send_digest(events)).await;
+    metrics.record_delivery(batch.len(), result.is_ok(), started.elapsed());
+
+    for held in batch {
+        let id = held.event.event_id.to_string();
+        if let Err(e) = settle(jetstream, config, metrics, &held.message, held.delivered, &id, &result).await {
+            tracing::error!("Failed to settle message: {}", e);
+        }
+    }
+}
+
+/// Pulls at most one message, waiting up to `expires` for it.
+async fn next_message(consumer: &PullConsumer, expires: Duration) -> Option<Message> {
+    let batch = consumer
+        .batch()
+        .max_messages(1)
+        .expires(expires)
+        .messages()
+        .await;
+
+    let mut batch = match batch {
+        Ok(batch) => batch,
+        Err(e) => {
+            tracing::warn!("Failed to pull from JetStream: {}", e);
+            tokio::time::sleep(FETCH_RETRY_DELAY).await;
+            return None;
+        }
+    };
+
+    match batch.next().await? {
+        Ok(message) => Some(message),
+        Err(e) => {
+            tracing::warn!("Failed to receive from JetStream: {}", e);
+            None
+        }
+    }
+}
+
+async fn handle<F, Fut>(
+    jetstream: &jetstream::Context,
+    config: &ConsumerConfig,
+    metrics: &SinkMetrics,
+    filter: &Filter,
+    message: &Message,
+    held: &[&Message],
+    handler: &F,
+) -> Result<(), Error>
+where
+    F: Fn(TodoEvent) -> Fut,
+    Fut: Future<Output = Result<(), Failure>>,
+{
+    let Some((event, delivered)) = decode(jetstream, metrics, message).await? else {
+        return Ok(());
+    };
+    if skip_filtered(metrics, filter, message, &event).await? {
+        return Ok(());
+    }
+
+    let event_id = event.event_id.to_string();
+    let started = Instant::now();
+    let result = with_progress(config, &[&[message], held].concat(), handler(event)).await;
+    metrics.record_delivery(1, result.is_ok(), started.elapsed());
+
+    settle(jetstream, config, metrics, message, delivered, &event_id, &result).await
+}
+
+/// Decodes a message into an event and its delivery count. Messages that
+/// cannot be handled by any retry are dead-lettered, returning `None`.
+async fn decode(
+    jetstream: &jetstream::Context,
+    metrics: &SinkMetrics,
+    message: &Message,
+) -> Result<Option<(TodoEvent, i64)>, Error> {
+    metrics.received.inc();
+
+    let delivered = message.info()?.delivered;
+
+    tracing::debug!("Received message: {}", String::from_utf8_lossy(&message.payload));
+
+    match serde_json::from_slice::<TodoEvent>(&message.payload) {
+        Ok(event) if event.is_supported() => {
+            metrics.parsed.inc();
+            Ok(Some((event, delivered)))
+        }
+        Ok(event) => {
+            let reason = format!("unsupported schema version {}", event.schema_version);
+            tracing::warn!("Dead-lettering event {}: {}", event.event_id, reason);
+            metrics.parse_failed.inc();
+            metrics.dead_lettered.inc();
+            dead_letter(jetstream, message, delivered, &reason).await?;
+            Ok(None)
+        }
+        Err(e) => {
+            let reason = format!("failed to parse todo event: {}", e);
+            tracing::error!("Dead-lettering message: {}", reason);
+            metrics.parse_failed.inc();
+            metrics.dead_lettered.inc();
+            dead_letter(jetstream, message, delivered, &reason).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Acks `message` when `filter` excludes its event, returning whether it did.
+/// Filtered events count as filtered only, never as delivered.
+async fn skip_filtered(
+    metrics: &SinkMetrics,
+    filter: &Filter,
+    message: &Message,
+    event: &TodoEvent,
+) -> Result<bool, Error> {
+    let Err(skip) = filter.check(event, Utc::now()) else {
+        return Ok(false);
+    };
+
+    tracing::debug!("Not sending event {}: {}", event.event_id, skip);
+    metrics.filtered.inc();
+    message.ack().await?;
+
+    Ok(true)
+}
+
+/// Acks, redelivers or dead-letters a message according to `result`.
+async fn settle(
+    jetstream: &jetstream::Context,
+    config: &ConsumerConfig,
+    metrics: &SinkMetrics,
+    message: &Message,
+    delivered: i64,
+    event_id: &str,
+    result: &Result<(), Failure>,
+) -> Result<(), Error> {
+    match result {
+        Ok(()) => {
+            message.ack().await?;
+        }
+        Err(e) if delivered >= config.max_deliver => {
+            tracing::error!(
+                "Dead-lettering event {} after {} failed deliveries: {}",
+                event_id,
+                delivered,
+                e
+            );
+            metrics.dead_lettered.inc();
+            dead_letter(jetstream, message, delivered, &e.reason).await?;
+        }
+        Err(e) => {
+            let delay = e.retry_after.unwrap_or_else(|| redelivery_delay(delivered));
+            tracing::warn!(
+                "Failed to handle event {} (delivery {}/{}), redelivering in {}s: {}",
+                event_id,
+                delivered,
+                config.max_deliver,
+                delay.as_secs(),
+                e
+            );
+            message.ack_with(AckKind::Nak(Some(delay))).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Drives `handling` to completion, telling the server every half ack wait
+/// that `messages` are still being worked on, so retries inside the handler
+/// do not cause a concurrent redelivery.
+async fn with_progress<T>(config: &ConsumerConfig, messages: &[&Message], handling: impl Future<Output = T>) -> T {
+    let period = config.ack_wait / 2;
+    let mut progress = tokio::time::interval_at(Instant::now() + period, period);
+    tokio::pin!(handling);
+
+    loop {
+        tokio::select! {
+            result = &mut handling => return result,
+            _ = progress.tick() => {
+                for message in messages {
+                    if let Err(e) = message.ack_with(AckKind::Progress).await {
+                        tracing::warn!("Failed to extend ack wait: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hands messages whose send was cut short by shutdown back to the server for
+/// immediate redelivery, instead of leaving them until their ack wait runs out.
+async fn abandon(messages: &[&Message]) {
+    for message in messages {
+        if let Err(e) = message.ack_with(AckKind::Nak(None)).await {
+            tracing::warn!("Failed to hand back message: {}", e);
+        }
+    }
+    tracing::warn!("Shutdown deadline passed, handed back {} message(s) for redelivery", messages.len());
+}
+
+/// Copies the message to the dead-letter subject and terminates it. If the
+/// copy fails the message is left unacked, so it is redelivered rather than
+/// lost.
+async fn dead_letter(
+    jetstream: &jetstream::Context,
+    message: &Message,
+    delivered: i64,
+    reason: &str,
+) -> Result<(), Error> {
+    let sequence = message.info()?.stream_sequence;
+
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert(DEAD_LETTER_REASON_HEADER, reason);
+    headers.insert(DELIVERY_COUNT_HEADER, delivered.to_string().as_str());
+    headers.insert(STREAM_SEQUENCE_HEADER, sequence.to_string().as_str());
+
+    jetstream
+        .publish_with_headers(todo_events::DEAD_LETTER_SUBJECT, headers, message.payload.clone())
+        .await?
+        .await?;
+
+    message.ack_with(AckKind::Term).await?;
+
+    Ok(())
+}
+
+fn redelivery_delay(delivered: i64) -> Duration {
+    let secs = 2_u64.saturating_pow(delivered.clamp(0, 32) as u32);
+    Duration::from_secs(secs).min(MAX_REDELIVERY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redelivery_delay_grows_and_is_capped() {
+        assert_eq!(redelivery_delay(1), Duration::from_secs(2));
+        assert_eq!(redelivery_delay(3), Duration::from_secs(8));
+        assert_eq!(redelivery_delay(40), MAX_REDELIVERY_DELAY);
+    }
+}
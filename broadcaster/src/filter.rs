@@ -0,0 +1,186 @@
+//! Per-sink rules deciding which events a sink is sent.
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use todo_events::{TodoAction, TodoEvent};
+
+use crate::config::{ConfigError, FilterConfig, QuietHoursConfig};
+
+/// Why an event was not sent to a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    Action,
+    Content,
+    QuietHours,
+}
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Skip::Action => "action not included",
+            Skip::Content => "content does not match",
+            Skip::QuietHours => "quiet hours",
+        })
+    }
+}
+
+/// A sink's filter. The default lets everything through.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    actions: Vec<TodoAction>,
+    content: Option<Regex>,
+    quiet_hours: Option<QuietHours>,
+}
+
+#[derive(Debug, Clone)]
+struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    timezone: Tz,
+}
+
+impl Filter {
+    pub fn from_config(config: &FilterConfig) -> Result<Self, ConfigError> {
+        if config.actions.contains(&TodoAction::Unknown) {
+            return Err(ConfigError::Invalid("filter lists an unknown action".to_string()));
+        }
+
+        let content = config
+            .content
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| ConfigError::Invalid(format!("invalid content regex: {}", e)))?;
+
+        let quiet_hours = config.quiet_hours.as_ref().map(QuietHours::from_config).transpose()?;
+
+        Ok(Self {
+            actions: config.actions.clone(),
+            content,
+            quiet_hours,
+        })
+    }
+
+    /// Whether `event` should be sent at `now`.
+    pub fn check(&self, event: &TodoEvent, now: DateTime<Utc>) -> Result<(), Skip> {
+        if !self.actions.is_empty() && !self.actions.contains(&event.action) {
+            return Err(Skip::Action);
+        }
+
+        if let Some(content) = &self.content {
+            if !content.is_match(&event.todo.content) {
+                return Err(Skip::Content);
+            }
+        }
+
+        if let Some(quiet_hours) = &self.quiet_hours {
+            if quiet_hours.contains(now) {
+                return Err(Skip::QuietHours);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl QuietHours {
+    fn from_config(config: &QuietHoursConfig) -> Result<Self, ConfigError> {
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| ConfigError::Invalid(format!("quiet hours time '{}' is not HH:MM", value)))
+        };
+
+        let start = time(&config.start)?;
+        let end = time(&config.end)?;
+        if start == end {
+            return Err(ConfigError::Invalid("quiet hours must not start and end at the same time".to_string()));
+        }
+
+        let timezone = config
+            .timezone
+            .parse()
+            .map_err(|_| ConfigError::Invalid(format!("unknown timezone '{}'", config.timezone)))?;
+
+        Ok(Self { start, end, timezone })
+    }
+
+    /// Whether `now` falls in the quiet period, which wraps past midnight
+    /// when it ends before it starts.
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.timezone).time();
+
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use todo_events::{Priority, TodoSnapshot};
+
+    use super::*;
+
+    fn event(action: TodoAction, content: &str) -> TodoEvent {
+        let now = Utc::now();
+        let todo = TodoSnapshot {
+            id: 1,
+            content: content.to_string(),
+            done: false,
+            created_at: now,
+            updated_at: now,
+            completed_at: None,
+            version: 1,
+            priority: Priority::Normal,
+            due_at: None,
+            tags: Vec::new(),
+            list_id: None,
+            deleted_at: None,
+        };
+        TodoEvent::new("test", action, todo, None)
+    }
+
+    fn filter(toml: &str) -> Filter {
+        Filter::from_config(&toml::from_str(toml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn filters_by_action_and_content() {
+        let filter = filter("actions = [\"created\", \"updated\"]\ncontent = \"(?i)urgent\"\n");
+        let now = Utc::now();
+
+        assert_eq!(filter.check(&event(TodoAction::Created, "URGENT: fix prod"), now), Ok(()));
+        assert_eq!(filter.check(&event(TodoAction::Created, "Buy milk"), now), Err(Skip::Content));
+        assert_eq!(filter.check(&event(TodoAction::Deleted, "urgent"), now), Err(Skip::Action));
+        assert_eq!(Filter::default().check(&event(TodoAction::Deleted, "Buy milk"), now), Ok(()));
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight_in_their_timezone() {
+        let filter = filter("[quiet_hours]\nstart = \"22:00\"\nend = \"07:00\"\ntimezone = \"Europe/Helsinki\"\n");
+        let event = event(TodoAction::Created, "Buy milk");
+
+        // Helsinki is UTC+3 in summer.
+        let at = |hour, minute| Utc.with_ymd_and_hms(2024, 7, 1, hour, minute, 0).unwrap();
+
+        assert_eq!(filter.check(&event, at(18, 59)), Ok(()));
+        assert_eq!(filter.check(&event, at(19, 0)), Err(Skip::QuietHours));
+        assert_eq!(filter.check(&event, at(2, 0)), Err(Skip::QuietHours));
+        assert_eq!(filter.check(&event, at(4, 0)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        let invalid = |toml: &str| Filter::from_config(&toml::from_str(toml).unwrap()).is_err();
+
+        assert!(invalid("content = \"(unclosed\""));
+        assert!(invalid("actions = [\"archived\"]"));
+        assert!(invalid("[quiet_hours]\nstart = \"25:00\"\nend = \"07:00\""));
+        assert!(invalid("[quiet_hours]\nstart = \"07:00\"\nend = \"07:00\""));
+        assert!(invalid("[quiet_hours]\nstart = \"22:00\"\nend = \"07:00\"\ntimezone = \"Mars/Olympus\""));
+    }
+}
@@ -0,0 +1,237 @@
+//! Leases kept in a JetStream key-value bucket, so that a sink which must not
+//! be consumed by several replicas at once is only consumed by one.
+//!
+//! Every replica shares a sink's durable consumer, which is fine for plain
+//! sends. A digest sink, though, collects its window in memory, and a
+//! Telegram rate limit is a token bucket in memory; with six replicas both
+//! would be split six ways. Such sinks name a lease (see [`Sink::lease`]) and
+//! only the replica holding it consumes them; sinks naming the same lease are
+//! consumed together.
+//!
+//! A lease is an entry that the holder rewrites every [`RENEW_INTERVAL`]. The
+//! bucket drops entries older than [`LEASE_TTL`], so the lease of a replica
+//! that died is free again after that long.
+//!
+//! [`Sink::lease`]: crate::Sink::lease
+
+use std::future::Future;
+use std::time::Duration;
+
+use async_nats::jetstream::kv::{self, CreateErrorKind};
+use async_nats::jetstream::{self, context::KeyValueErrorKind};
+use tokio::time::Instant;
+
+use crate::consumer::Error;
+use crate::shutdown::Shutdown;
+
+pub const BUCKET: &str = "broadcaster_leases";
+/// How long a lease outlives its last renewal.
+pub const LEASE_TTL: Duration = Duration::from_secs(30);
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Gets the lease bucket, creating it if needed.
+pub async fn bucket(jetstream: &jetstream::Context) -> Result<kv::Store, Error> {
+    match jetstream.get_key_value(BUCKET).await {
+        Ok(store) => Ok(store),
+        Err(e) if e.kind() == KeyValueErrorKind::GetBucket => {
+            let store = jetstream
+                .create_key_value(kv::Config {
+                    bucket: BUCKET.to_string(),
+                    description: "Which broadcaster replica consumes each leased sink".to_string(),
+                    history: 1,
+                    max_age: LEASE_TTL,
+                    ..Default::default()
+                })
+                .await?;
+            Ok(store)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Names this process as a lease holder; only shown to operators looking at
+/// the bucket.
+pub fn holder_name() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "broadcaster".to_string());
+    format!("{}-{}", host, std::process::id())
+}
+
+/// Groups `items` by the lease each needs, in order of first appearance.
+///
+/// A lease is taken once per process, so sinks sharing one (Telegram sinks
+/// posting to the same chat) must run together under a single holder: taken
+/// separately, the first would keep the others waiting for good. Items
+/// without a lease each get a group of their own.
+pub fn group_by_lease<T>(
+    items: impl IntoIterator<Item = T>,
+    lease: impl Fn(&T) -> Option<&str>,
+) -> Vec<(Option<String>, Vec<T>)> {
+    let mut groups: Vec<(Option<String>, Vec<T>)> = Vec::new();
+
+    for item in items {
+        let Some(key) = lease(&item).map(str::to_string) else {
+            groups.push((None, vec![item]));
+            continue;
+        };
+
+        match groups.iter_mut().find(|(group_key, _)| group_key.as_ref() == Some(&key)) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((Some(key), vec![item])),
+        }
+    }
+
+    groups
+}
+
+/// Runs `work` whenever this replica holds the lease `key`, until `shutdown`
+/// is requested.
+///
+/// `work` gets a [`Shutdown`] that is triggered on shutdown or when a renewal
+/// fails, and must return soon after; it then still has the rest of the
+/// lease, at least `LEASE_TTL - RENEW_INTERVAL`, before another replica can
+/// take over. A lease given up on shutdown is released right away.
+pub async fn run_exclusively<F, Fut>(
+    store: &kv::Store,
+    key: &str,
+    holder: &str,
+    shutdown: &Shutdown,
+    work: F,
+) -> Result<(), Error>
+where
+    F: Fn(Shutdown) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    loop {
+        let Some(mut revision) = acquire(store, key, holder, shutdown).await else {
+            return Ok(());
+        };
+        tracing::info!("Took lease '{}'", key);
+
+        let stop = Shutdown::new();
+        let working = work(stop.clone());
+        tokio::pin!(working);
+
+        let mut renewal = tokio::time::interval_at(Instant::now() + RENEW_INTERVAL, RENEW_INTERVAL);
+        let mut held = true;
+
+        let result = loop {
+            tokio::select! {
+                result = &mut working => break result,
+                _ = shutdown.requested(), if !stop.is_requested() => stop.trigger(),
+                _ = renewal.tick(), if !stop.is_requested() => {
+                    match store.update(key, holder.to_string().into(), revision).await {
+                        Ok(next) => revision = next,
+                        Err(e) => {
+                            tracing::warn!("Failed to renew lease '{}', stopping: {}", key, e);
+                            held = false;
+                            stop.trigger();
+                        }
+                    }
+                }
+            }
+        };
+
+        if held {
+            match store.delete_expect_revision(key, Some(revision)).await {
+                Ok(()) => tracing::info!("Released lease '{}'", key),
+                Err(e) => tracing::warn!("Failed to release lease '{}': {}", key, e),
+            }
+        }
+
+        result?;
+
+        if shutdown.is_requested() {
+            return Ok(());
+        }
+    }
+}
+
+/// Waits for `key` to be free and takes it, returning its revision, or `None`
+/// once shutdown is requested.
+async fn acquire(store: &kv::Store, key: &str, holder: &str, shutdown: &Shutdown) -> Option<u64> {
+    let mut waiting = false;
+
+    loop {
+        if shutdown.is_requested() {
+            return None;
+        }
+
+        match store.create(key, holder.to_string().into()).await {
+            Ok(revision) => return Some(revision),
+            Err(e) if e.kind() == CreateErrorKind::AlreadyExists => {
+                if !waiting {
+                    tracing::info!("Lease '{}' is held by another replica, waiting for it", key);
+                    waiting = true;
+                }
+            }
+            Err(e) => tracing::warn!("Failed to take lease '{}': {}", key, e),
+        }
+
+        tokio::select! {
+            _ = shutdown.requested() => return None,
+            _ = tokio::time::sleep(RENEW_INTERVAL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::{Sink, Templates};
+
+    #[test]
+    fn sinks_on_the_same_chat_share_one_group() {
+        let config = Config::parse(
+            r#"
+            [[sinks]]
+            name = "alerts"
+            type = "telegram"
+            bot_token = "123:abc"
+            chat_id = "42"
+
+            [[sinks]]
+            type = "stdout"
+
+            [[sinks]]
+            name = "daily"
+            type = "telegram"
+            bot_token = "123:abc"
+            chat_id = "42"
+
+            [sinks.digest]
+            window_secs = 86400
+
+            [[sinks]]
+            name = "other-chat"
+            type = "telegram"
+            bot_token = "123:abc"
+            chat_id = "7"
+            "#,
+        )
+        .unwrap();
+        let templates = Arc::new(Templates::builtin());
+        let sinks: Vec<Sink> = config
+            .sinks
+            .iter()
+            .map(|sink| Sink::from_config(sink, &templates).unwrap())
+            .collect();
+
+        let groups = group_by_lease(&sinks, |sink| sink.lease.as_deref());
+        let names: Vec<(Option<&str>, Vec<&str>)> = groups
+            .iter()
+            .map(|(key, group)| (key.as_deref(), group.iter().map(|sink| sink.name.as_str()).collect()))
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                (Some("telegram-chat-3432"), vec!["alerts", "daily"]),
+                (None, vec!["stdout"]),
+                (Some("telegram-chat-37"), vec!["other-chat"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+pub mod config;
+pub mod consumer;
+pub mod filter;
+pub mod lease;
+pub mod metrics;
+pub mod notifier;
+pub mod rate_limit;
+pub mod server;
+pub mod shutdown;
+pub mod templates;
+
+pub use config::Config;
+pub use consumer::ConsumerConfig;
+pub use filter::Filter;
+pub use metrics::{Metrics, SinkMetrics};
+pub use notifier::{Notifier, Sink};
+pub use server::Health;
+pub use shutdown::Shutdown;
+pub use templates::{Rendered, Templates};
+
+/// Connects with the settings from the environment (see
+/// [`todo_events::nats`]). Returns at once and keeps connecting in the
+/// background, so health and metrics are served while NATS is unreachable.
+pub async fn connect_to_nats() -> Result<(async_nats::Client, todo_events::nats::Status), todo_events::nats::Error> {
+    let config = todo_events::nats::ConnectConfig::from_env()?;
+    todo_events::nats::connect_in_background(&config).await
+}
@@ -1,61 +1,20 @@
-use futures::stream::StreamExt;
-use serde::{Deserialize, Serialize};
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use async_nats::jetstream::{self, consumer::PullConsumer};
+use futures::future::try_join_all;
 use tracing_subscriber::fmt::time::UtcTime;
+use todo_events::TodoEvent;
 use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Todo {
-    id: i32,
-    content: String,
-    done: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TodoEvent {
-    action: String,
-    todo: Todo,
-}
-
-#[derive(Debug, Clone, Serialize)]
-struct TelegramMessage {
-    chat_id: String,
-    text: String,
-}
-
-async fn connect_to_nats() -> Result<async_nats::Client, async_nats::ConnectError> {
-    let nats_url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://my-nats:4222".to_string());
-
-    tracing::info!("Connecting to NATS at {}", nats_url);
-
-    let client = async_nats::connect(&nats_url).await?;
-
-    tracing::info!("Connected to NATS successfully");
-
-    Ok(client)
-}
-
-async fn send_telegram(bot_token: &str, chat_id: &str, text: String) -> Result<(), reqwest::Error> {
-    let client = reqwest::Client::new();
-    let url = format!("https://api.telegram.org/bot{}/sendMessage", bot_token);
-
-    let message = TelegramMessage {
-        chat_id: chat_id.to_string(),
-        text,
-    };
-
-    tracing::info!("Sending Telegram message: {}", message.text);
-
-    client
-        .post(&url)
-        .json(&message)
-        .send()
-        .await?;
-
-    Ok(())
-}
+use broadcaster::consumer::Failure;
+use broadcaster::{
+    connect_to_nats, consumer, lease, server, shutdown, Config, ConsumerConfig, Health, Metrics, Shutdown, Sink, SinkMetrics,
+    Templates,
+};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
     tracing_subscriber::fmt()
@@ -63,54 +22,168 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_env_filter(filter)
         .init();
 
-    let is_staging = std::env::var("STAGING").unwrap_or_else(|_| "false".to_string()) == "true";
-
-    let (telegram_bot_token, telegram_chat_id) = if is_staging {
-        tracing::info!("Broadcaster starting in STAGING mode (logs only)");
-        (String::new(), String::new())
-    } else {
-        let token = std::env::var("TELEGRAM_BOT_TOKEN")
-            .expect("TELEGRAM_BOT_TOKEN environment variable must be set");
-        let chat_id = std::env::var("TELEGRAM_CHAT_ID")
-            .expect("TELEGRAM_CHAT_ID environment variable must be set");
-        tracing::info!("Broadcaster starting with Telegram chat ID: {}", chat_id);
-        (token, chat_id)
-    };
+    let config = Config::from_env()?;
 
-    let client = connect_to_nats().await?;
+    let templates = Arc::new(match config.templates_path() {
+        Some(path) => {
+            tracing::info!("Loading templates from {}", path.display());
+            Templates::load(path)?
+        }
+        None => Templates::builtin(),
+    });
+
+    let sinks = config
+        .sinks
+        .iter()
+        .map(|sink| Sink::from_config(sink, &templates))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let consumer_config = ConsumerConfig::from_env()?;
+
+    let metrics = Metrics::new();
+
+    let port = std::env::var("PORT")
+        .unwrap_or_else(|_| "3000".to_string())
+        .parse::<u16>()
+        .expect("PORT must be a valid port number");
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+
+    // Returns at once, so /livez and /metrics are served while NATS is down.
+    let (client, nats_status) = connect_to_nats().await?;
+    let health = Health::new((client.clone(), nats_status.clone()));
+
+    let server = server::run(listener, health.clone(), metrics.clone())?;
+    let server_handle = server.handle();
+    tokio::spawn(server);
+
+    tracing::info!("Health and metrics server started on port {}", port);
+
+    // As when connecting in the foreground, running out of attempts at the
+    // first connection stops the process.
+    tokio::spawn({
+        let nats_status = nats_status.clone();
+        async move {
+            nats_status.given_up().await;
+            tracing::error!("Failed to connect to NATS, giving up");
+            std::process::exit(1);
+        }
+    });
 
-    let mut subscriber = client.queue_subscribe("todo.events", "broadcasters".to_string()).await?;
+    let jetstream = jetstream::new(client.clone());
 
-    tracing::info!("Subscribed to todo.events with queue group 'broadcasters'");
+    // Creating the consumers needs JetStream.
+    nats_status.connected().await;
 
-    while let Some(message) = subscriber.next().await {
-        let payload = String::from_utf8_lossy(&message.payload);
+    let mut consumers = Vec::with_capacity(sinks.len());
 
-        tracing::debug!("Received message: {}", payload);
+    for sink in &sinks {
+        let config = ConsumerConfig {
+            durable_name: format!("{}-{}", consumer_config.durable_name, sink.name),
+            ..consumer_config.clone()
+        };
+        let consumer = consumer::create_consumer(&jetstream, &config).await?;
 
-        match serde_json::from_str::<TodoEvent>(&payload) {
-            Ok(event) => {
-                let message_text = match event.action.as_str() {
-                    "created" => format!("A todo was created: \"{}\"", event.todo.content),
-                    "updated" => format!("A todo was updated: \"{}\" (done: {})", event.todo.content, event.todo.done),
-                    _ => format!("Unknown action on todo: \"{}\"", event.todo.content),
-                };
+        tracing::info!("Broadcasting to sink '{}'", sink.name);
+
+        consumers.push((sink, config, consumer, metrics.sink(&sink.name)));
+    }
 
-                if is_staging {
-                    tracing::info!("STAGING: {}", message_text);
-                } else {
-                    if let Err(e) = send_telegram(&telegram_bot_token, &telegram_chat_id, message_text).await {
-                        tracing::error!("Failed to send Telegram message: {}", e);
-                    } else {
-                        tracing::info!("Successfully sent Telegram notification");
-                    }
-                }
+    let leases = match sinks.iter().any(|sink| sink.lease.is_some()) {
+        true => Some(lease::bucket(&jetstream).await?),
+        false => None,
+    };
+    let holder = lease::holder_name();
+
+    health.set_ready(true);
+
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        let health = health.clone();
+        async move {
+            shutdown::signal().await;
+            tracing::info!(
+                "Shutting down, waiting up to {}s for in-flight notifications",
+                consumer_config.shutdown_timeout.as_secs()
+            );
+            health.set_ready(false);
+            shutdown.trigger();
+        }
+    });
+
+    // Sinks sharing a lease run under one holder; see `lease::group_by_lease`.
+    let groups = lease::group_by_lease(&consumers, |(sink, ..)| sink.lease.as_deref());
+
+    try_join_all(groups.into_iter().map(|(key, group)| {
+        let (jetstream, leases, holder, shutdown) = (&jetstream, &leases, &holder, &shutdown);
+        let broadcast = move |shutdown: Shutdown| {
+            let group = group.clone();
+            async move {
+                try_join_all(group.into_iter().map(|(sink, config, consumer, metrics)| {
+                    broadcast(jetstream, sink, config, consumer, metrics, &shutdown)
+                }))
+                .await
+                .map(drop)
             }
-            Err(e) => {
-                tracing::error!("Failed to parse todo event: {}", e);
+        };
+
+        async move {
+            match (key, leases) {
+                (Some(key), Some(store)) => lease::run_exclusively(store, &key, holder, shutdown, broadcast).await,
+                _ => broadcast(shutdown.clone()).await,
             }
         }
+    }))
+    .await?;
+
+    // Drops the pull subscriptions and flushes pending acks before closing.
+    if let Err(e) = client.drain().await {
+        tracing::warn!("Failed to drain NATS connection: {}", e);
     }
+    server_handle.stop(true).await;
+
+    tracing::info!("Broadcaster stopped");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn broadcast(
+    jetstream: &jetstream::Context,
+    sink: &Sink,
+    config: &ConsumerConfig,
+    consumer: &PullConsumer,
+    metrics: &SinkMetrics,
+    shutdown: &Shutdown,
+) -> Result<(), consumer::Error> {
+    let send_one = move |event: TodoEvent| async move {
+        let message = sink.templates.render(&event).map_err(|e| e.to_string())?;
+        sink.notifier.notify(&event, &message).await.map_err(Failure::from)?;
+        tracing::info!("Sent event {} to sink '{}'", event.event_id, sink.name);
+        Ok::<_, Failure>(())
+    };
+
+    let send_digest = move |events: Vec<TodoEvent>| async move {
+        let message = sink.templates.render_digest(&events).map_err(|e| e.to_string())?;
+        sink.notifier.notify_digest(&events, &message).await.map_err(Failure::from)?;
+        tracing::info!("Sent digest of {} events to sink '{}'", events.len(), sink.name);
+        Ok::<_, Failure>(())
+    };
+
+    match &sink.digest {
+        Some(digest) => {
+            consumer::run_digest(
+                jetstream,
+                consumer,
+                config,
+                metrics,
+                shutdown,
+                &sink.filter,
+                digest,
+                send_one,
+                send_digest,
+            )
+            .await
+        }
+        None => consumer::run(jetstream, consumer, config, metrics, shutdown, &sink.filter, send_one).await,
+    }
+}
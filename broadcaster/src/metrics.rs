@@ -0,0 +1,157 @@
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+
+const LATENCY_BUCKETS: [f64; 11] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Prometheus metrics of every sink, served at `/metrics`.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    received: IntCounterVec,
+    parsed: IntCounterVec,
+    parse_failed: IntCounterVec,
+    delivered: IntCounterVec,
+    delivery_failed: IntCounterVec,
+    dead_lettered: IntCounterVec,
+    filtered: IntCounterVec,
+    delivery_duration: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounterVec::new(Opts::new(name, help), &["sink"]).expect("valid metric");
+            registry.register(Box::new(counter.clone())).expect("metric registered once");
+            counter
+        };
+
+        let received = counter("broadcaster_events_received_total", "Messages received from JetStream.");
+        let parsed = counter("broadcaster_events_parsed_total", "Messages decoded into a supported TodoEvent.");
+        let parse_failed = counter(
+            "broadcaster_events_parse_failed_total",
+            "Messages that could not be decoded or have an unsupported schema version.",
+        );
+        let delivered = counter("broadcaster_events_delivered_total", "Events delivered to the sink.");
+        let delivery_failed = counter(
+            "broadcaster_events_delivery_failed_total",
+            "Failed delivery attempts; the event is redelivered or dead-lettered.",
+        );
+        let dead_lettered = counter(
+            "broadcaster_events_dead_lettered_total",
+            "Events moved to the dead-letter subject.",
+        );
+        let filtered = counter(
+            "broadcaster_events_filtered_total",
+            "Events acked without sending because the sink's filter excluded them.",
+        );
+
+        let delivery_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "broadcaster_delivery_duration_seconds",
+                "Time to deliver one notification or digest, including retries.",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["sink", "outcome"],
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(delivery_duration.clone()))
+            .expect("metric registered once");
+
+        Self {
+            registry,
+            received,
+            parsed,
+            parse_failed,
+            delivered,
+            delivery_failed,
+            dead_lettered,
+            filtered,
+            delivery_duration,
+        }
+    }
+
+    /// Handles with the `sink` label filled in.
+    pub fn sink(&self, sink: &str) -> SinkMetrics {
+        SinkMetrics {
+            received: self.received.with_label_values(&[sink]),
+            parsed: self.parsed.with_label_values(&[sink]),
+            parse_failed: self.parse_failed.with_label_values(&[sink]),
+            delivered: self.delivered.with_label_values(&[sink]),
+            delivery_failed: self.delivery_failed.with_label_values(&[sink]),
+            dead_lettered: self.dead_lettered.with_label_values(&[sink]),
+            filtered: self.filtered.with_label_values(&[sink]),
+            success_duration: self.delivery_duration.with_label_values(&[sink, "success"]),
+            failure_duration: self.delivery_duration.with_label_values(&[sink, "failure"]),
+        }
+    }
+
+    /// Everything registered, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding cannot fail");
+        String::from_utf8(buffer).expect("text format is UTF-8")
+    }
+}
+
+/// Metrics of one sink's consumer.
+#[derive(Clone)]
+pub struct SinkMetrics {
+    pub received: prometheus::IntCounter,
+    pub parsed: prometheus::IntCounter,
+    pub parse_failed: prometheus::IntCounter,
+    pub delivered: prometheus::IntCounter,
+    pub delivery_failed: prometheus::IntCounter,
+    pub dead_lettered: prometheus::IntCounter,
+    pub filtered: prometheus::IntCounter,
+    pub success_duration: prometheus::Histogram,
+    pub failure_duration: prometheus::Histogram,
+}
+
+impl SinkMetrics {
+    /// Records a delivery of `events` events that took `duration`.
+    pub fn record_delivery(&self, events: usize, succeeded: bool, duration: std::time::Duration) {
+        if succeeded {
+            self.delivered.inc_by(events as u64);
+            self.success_duration.observe(duration.as_secs_f64());
+        } else {
+            self.delivery_failed.inc_by(events as u64);
+            self.failure_duration.observe(duration.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn counts_are_rendered_per_sink() {
+        let metrics = Metrics::new();
+        let telegram = metrics.sink("telegram");
+
+        telegram.received.inc();
+        telegram.record_delivery(3, true, Duration::from_millis(200));
+        metrics.sink("audit").record_delivery(1, false, Duration::from_secs(2));
+
+        let text = metrics.render();
+
+        assert!(text.contains("broadcaster_events_received_total{sink=\"telegram\"} 1"));
+        assert!(text.contains("broadcaster_events_delivered_total{sink=\"telegram\"} 3"));
+        assert!(text.contains("broadcaster_events_delivery_failed_total{sink=\"audit\"} 1"));
+        assert!(text.contains(
+            "broadcaster_delivery_duration_seconds_bucket{outcome=\"success\",sink=\"telegram\",le=\"0.25\"} 1"
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+use todo_events::TodoEvent;
+
+use super::{check_status, http_client, Notifier, NotifyError, REQUEST_TIMEOUT};
+use crate::templates::Rendered;
+
+/// Incoming-webhook dialects that take a single text field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatFlavor {
+    Slack,
+    Discord,
+}
+
+impl ChatFlavor {
+    /// Longest text the webhook accepts in one message, if it is short enough
+    /// for a digest to reach it.
+    fn max_message_len(&self) -> Option<usize> {
+        match self {
+            Self::Slack => None,
+            Self::Discord => Some(2000),
+        }
+    }
+}
+
+/// Posts the notification text to a Slack or Discord incoming webhook.
+#[derive(Debug, Clone)]
+pub struct ChatWebhook {
+    client: reqwest::Client,
+    flavor: ChatFlavor,
+    url: String,
+}
+
+impl ChatWebhook {
+    pub fn new(flavor: ChatFlavor, url: String) -> Self {
+        Self {
+            client: http_client(REQUEST_TIMEOUT),
+            flavor,
+            url,
+        }
+    }
+
+    /// Gives up on requests after `timeout` rather than the default 10s.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = http_client(timeout);
+        self
+    }
+}
+
+impl ChatWebhook {
+    /// Posts `message`, split into several messages in order if it is longer
+    /// than the webhook allows.
+    async fn post_rendered(&self, message: &Rendered) -> Result<(), NotifyError> {
+        let parts = match self.flavor.max_message_len() {
+            Some(max_len) => message.split(max_len),
+            None => vec![message.clone()],
+        };
+
+        for part in &parts {
+            self.post(part).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn post(&self, message: &Rendered) -> Result<(), NotifyError> {
+        let body = match self.flavor {
+            ChatFlavor::Slack => json!({ "text": message.text }),
+            ChatFlavor::Discord => json!({ "content": message.text }),
+        };
+
+        check_status(self.client.post(&self.url).json(&body).send().await?).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for ChatWebhook {
+    async fn notify(&self, _event: &TodoEvent, message: &Rendered) -> Result<(), NotifyError> {
+        self.post_rendered(message).await
+    }
+
+    async fn notify_digest(&self, _events: &[TodoEvent], message: &Rendered) -> Result<(), NotifyError> {
+        self.post_rendered(message).await
+    }
+}
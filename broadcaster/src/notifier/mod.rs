@@ -0,0 +1,158 @@
+//! Destinations for todo notifications.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use todo_events::TodoEvent;
+
+use crate::config::{ConfigError, DigestConfig, SinkConfig, SinkKind};
+use crate::filter::Filter;
+use crate::templates::{Rendered, Templates};
+
+mod chat;
+mod smtp;
+mod stdout;
+mod telegram;
+mod webhook;
+
+pub use chat::{ChatFlavor, ChatWebhook};
+pub use smtp::Smtp;
+pub use stdout::Stdout;
+pub use telegram::Telegram;
+pub use webhook::{Webhook, SIGNATURE_HEADER};
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotifyError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server responded with {status}: {body}")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("rate limited, retry after {}s", retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    #[error("failed to build email: {0}")]
+    Email(String),
+    #[error("SMTP delivery failed: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("failed to write notification: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl NotifyError {
+    /// Whether trying again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Http(e) => !e.is_builder(),
+            Self::Status { status, .. } => {
+                status.is_server_error() || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+            }
+            Self::RateLimited { .. } | Self::Smtp(_) | Self::Io(_) => true,
+            Self::Email(_) => false,
+        }
+    }
+
+    /// How long the server asked us to wait before the next attempt.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl From<NotifyError> for crate::consumer::Failure {
+    fn from(error: NotifyError) -> Self {
+        Self {
+            retry_after: error.retry_after(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Delivers one notification about `event`, rendered as `message` for
+    /// sinks that show text rather than the event itself.
+    async fn notify(&self, event: &TodoEvent, message: &Rendered) -> Result<(), NotifyError>;
+
+    /// Delivers one message summarising `events`, oldest first.
+    async fn notify_digest(&self, events: &[TodoEvent], message: &Rendered) -> Result<(), NotifyError>;
+}
+
+/// A configured notifier, the name it was configured under and the
+/// templates its messages are rendered with.
+pub struct Sink {
+    pub name: String,
+    pub notifier: Box<dyn Notifier>,
+    pub templates: Arc<Templates>,
+    pub digest: Option<DigestConfig>,
+    pub filter: Filter,
+    /// The [lease](crate::lease) a replica must hold to consume this sink,
+    /// for sinks whose state lives in memory: a digest's window, or the rate
+    /// limit of a Telegram chat. Sinks posting to the same chat share it and
+    /// are run together by its holder (see
+    /// [`group_by_lease`](crate::lease::group_by_lease)), so their sends
+    /// share one rate limit.
+    pub lease: Option<String>,
+}
+
+impl Sink {
+    /// Builds the sink, loading its own templates file if it names one and
+    /// using `default_templates` otherwise.
+    pub fn from_config(config: &SinkConfig, default_templates: &Arc<Templates>) -> Result<Self, ConfigError> {
+        let notifier: Box<dyn Notifier> = match &config.kind {
+            SinkKind::Telegram(telegram) => Box::new(Telegram::from_config(telegram)),
+            SinkKind::Webhook { url, secret } => Box::new(Webhook::new(url.clone(), secret.clone())),
+            SinkKind::Slack { url } => Box::new(ChatWebhook::new(ChatFlavor::Slack, url.clone())),
+            SinkKind::Discord { url } => Box::new(ChatWebhook::new(ChatFlavor::Discord, url.clone())),
+            SinkKind::Smtp(smtp) => Box::new(Smtp::from_config(smtp)?),
+            SinkKind::Stdout {} => Box::new(Stdout),
+        };
+
+        let templates = match &config.templates {
+            Some(path) => Arc::new(Templates::load(path)?),
+            None => default_templates.clone(),
+        };
+
+        let lease = match &config.kind {
+            SinkKind::Telegram(telegram) => Some(format!("telegram-chat-{}", hex::encode(&telegram.chat_id))),
+            _ => config.digest.as_ref().map(|_| format!("digest-{}", config.name())),
+        };
+
+        Ok(Self {
+            name: config.name().to_string(),
+            notifier,
+            templates,
+            digest: config.digest.clone(),
+            filter: config.filter.as_ref().map(Filter::from_config).transpose()?.unwrap_or_default(),
+            lease,
+        })
+    }
+}
+
+/// How long an HTTP sink waits for a response. Without a limit, one hung
+/// endpoint would stall its sink for good, as the consumer keeps extending
+/// the ack deadline while a send is in progress.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Turns a non-success response into [`NotifyError::Status`].
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, NotifyError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    Err(NotifyError::Status { status, body })
+}
@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use todo_events::TodoEvent;
+
+use super::{Notifier, NotifyError};
+use crate::config::{ConfigError, SmtpConfig, SmtpTls};
+use crate::templates::{DigestCounts, Rendered, TextFormat};
+
+/// Emails each notification to a fixed list of recipients.
+#[derive(Clone)]
+pub struct Smtp {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Smtp {
+    pub fn from_config(config: &SmtpConfig) -> Result<Self, ConfigError> {
+        let invalid = |e: &dyn std::fmt::Display| ConfigError::Invalid(format!("smtp: {}", e));
+
+        let mut builder = match config.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(|e| invalid(&e))?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host).map_err(|e| invalid(&e))?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+
+        if let Some(username) = &config.username {
+            let password = config.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        let from = config.from.parse().map_err(|e| invalid(&e))?;
+        let to = config
+            .to
+            .iter()
+            .map(|address| address.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(&e))?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+}
+
+impl Smtp {
+    async fn send(&self, subject: String, message: &Rendered) -> Result<(), NotifyError> {
+        let mut email = Message::builder()
+            .from(self.from.clone())
+            .subject(subject)
+            .header(match message.format {
+                TextFormat::Html => ContentType::TEXT_HTML,
+                TextFormat::Plain | TextFormat::MarkdownV2 => ContentType::TEXT_PLAIN,
+            });
+
+        for to in &self.to {
+            email = email.to(to.clone());
+        }
+
+        let email = email
+            .body(message.text.clone())
+            .map_err(|e| NotifyError::Email(e.to_string()))?;
+
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Smtp {
+    async fn notify(&self, event: &TodoEvent, message: &Rendered) -> Result<(), NotifyError> {
+        self.send(format!("Todo {}: {}", event.action, event.todo.content), message).await
+    }
+
+    async fn notify_digest(&self, events: &[TodoEvent], message: &Rendered) -> Result<(), NotifyError> {
+        let summary = DigestCounts::of(events).summary();
+        self.send(format!("Todo digest: {}", summary), message).await
+    }
+}
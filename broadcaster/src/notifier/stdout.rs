@@ -0,0 +1,29 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+use todo_events::TodoEvent;
+
+use super::{Notifier, NotifyError};
+use crate::templates::Rendered;
+
+/// Prints each notification as a line on stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl Stdout {
+    fn notify_text(&self, message: &Rendered) -> Result<(), NotifyError> {
+        writeln!(std::io::stdout().lock(), "{}", message.text)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Stdout {
+    async fn notify(&self, _event: &TodoEvent, message: &Rendered) -> Result<(), NotifyError> {
+        self.notify_text(message)
+    }
+
+    async fn notify_digest(&self, _events: &[TodoEvent], message: &Rendered) -> Result<(), NotifyError> {
+        self.notify_text(message)
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use todo_events::TodoEvent;
+
+use super::{http_client, Notifier, NotifyError, REQUEST_TIMEOUT};
+use crate::config::TelegramConfig;
+use crate::rate_limit::{self, TokenBucket};
+use crate::templates::{Rendered, TextFormat};
+
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Longer waits are left to the consumer's redelivery rather than blocking
+/// the sink.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Longest text the Bot API accepts in one message, in UTF-16 code units.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Serialize)]
+struct TelegramMessage {
+    chat_id: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'static str>,
+}
+
+/// Error body of the Bot API, e.g. `{"ok":false,"error_code":429,
+/// "description":"Too Many Requests: retry after 5","parameters":{"retry_after":5}}`.
+#[derive(Debug, Default, Deserialize)]
+struct ApiError {
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+/// Sends messages to a single Telegram chat through the Bot API, retrying
+/// transient failures and staying under the chat's rate limit.
+#[derive(Debug, Clone)]
+pub struct Telegram {
+    client: reqwest::Client,
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+    max_attempts: u32,
+    bucket: Arc<TokenBucket>,
+}
+
+impl Telegram {
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self::from_config(&TelegramConfig::new(bot_token, chat_id))
+    }
+
+    pub fn from_config(config: &TelegramConfig) -> Self {
+        Self {
+            client: http_client(REQUEST_TIMEOUT),
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            bot_token: config.bot_token.clone(),
+            chat_id: config.chat_id.clone(),
+            max_attempts: config.max_attempts.max(1),
+            bucket: rate_limit::for_chat(&config.chat_id, config.rate_limit_per_minute, config.burst),
+        }
+    }
+
+    /// Points the client at another Bot API server, e.g. a local mock.
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sends `message`, split into several messages in order if it is longer
+    /// than Telegram allows, as a full digest can be.
+    pub async fn send(&self, message: &Rendered) -> Result<(), NotifyError> {
+        for part in message.split(MAX_MESSAGE_LEN) {
+            self.send_part(part.text, part.format).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send_part(&self, text: String, format: TextFormat) -> Result<(), NotifyError> {
+        let message = TelegramMessage {
+            chat_id: self.chat_id.clone(),
+            text,
+            parse_mode: format.telegram_parse_mode(),
+        };
+
+        tracing::info!("Sending Telegram message: {}", message.text);
+
+        let mut attempt = 1;
+
+        loop {
+            self.bucket.acquire().await;
+
+            let error = match self.post(&message).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            let delay = match error.retry_after() {
+                Some(retry_after) => {
+                    // Every sender to this chat waits, not just this one.
+                    self.bucket.pause(retry_after);
+                    retry_after
+                }
+                None => backoff(attempt),
+            };
+
+            if !error.is_transient() || attempt >= self.max_attempts || delay > MAX_RETRY_DELAY {
+                return Err(error);
+            }
+
+            tracing::warn!(
+                "Telegram send failed (attempt {}/{}), retrying in {}ms: {}",
+                attempt,
+                self.max_attempts,
+                delay.as_millis(),
+                error
+            );
+
+            if error.retry_after().is_none() {
+                tokio::time::sleep(delay).await;
+            }
+
+            attempt += 1;
+        }
+    }
+
+    async fn post(&self, message: &TelegramMessage) -> Result<(), NotifyError> {
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.bot_token);
+
+        let response = self.client.post(&url).json(message).send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let retry_after_header = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let body = response.text().await.unwrap_or_default();
+        let api_error: ApiError = serde_json::from_str(&body).unwrap_or_default();
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let secs = api_error
+                .parameters
+                .and_then(|parameters| parameters.retry_after)
+                .or(retry_after_header)
+                .unwrap_or(1);
+            return Err(NotifyError::RateLimited {
+                retry_after: Duration::from_secs(secs),
+            });
+        }
+
+        Err(NotifyError::Status {
+            status,
+            body: api_error.description.unwrap_or(body),
+        })
+    }
+}
+
+/// Exponential backoff with jitter: half of the delay is fixed, the other
+/// half random, so concurrent senders spread out.
+fn backoff(attempt: u32) -> Duration {
+    let exponential = BASE_RETRY_DELAY.saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)));
+    let capped = exponential.min(MAX_RETRY_DELAY);
+    let half = capped / 2;
+    half + half.mul_f64(fastrand::f64())
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    async fn notify(&self, _event: &TodoEvent, message: &Rendered) -> Result<(), NotifyError> {
+        self.send(message).await
+    }
+
+    async fn notify_digest(&self, _events: &[TodoEvent], message: &Rendered) -> Result<(), NotifyError> {
+        self.send(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_within_jitter_bounds() {
+        for attempt in 1..=10 {
+            let ceiling = BASE_RETRY_DELAY
+                .saturating_mul(2_u32.pow(attempt - 1))
+                .min(MAX_RETRY_DELAY);
+            let delay = backoff(attempt);
+
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Token bucket refilled continuously at `per_minute` tokens per minute and
+/// holding at most `burst`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    updated: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            refill_per_sec: f64::from(per_minute.max(1)) / 60.0,
+            state: Mutex::new(State {
+                tokens: capacity,
+                updated: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a token is available, then takes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+
+                match state.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        let elapsed = now.duration_since(state.updated).as_secs_f64();
+                        state.tokens = (state.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+                        state.updated = now;
+
+                        if state.tokens >= 1.0 {
+                            state.tokens -= 1.0;
+                            return;
+                        }
+
+                        Duration::from_secs_f64((1.0 - state.tokens) / self.refill_per_sec)
+                    }
+                }
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Holds back every caller for `duration` and empties the bucket, e.g.
+    /// after the server asked us to slow down.
+    pub fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+
+        state.paused_until = Some(state.paused_until.map_or(until, |current| current.max(until)));
+        state.tokens = 0.0;
+        state.updated = until;
+    }
+}
+
+static CHAT_BUCKETS: LazyLock<Mutex<HashMap<String, Arc<TokenBucket>>>> = LazyLock::new(Default::default);
+
+/// The bucket shared by every sink posting to `chat_id` in this process. The
+/// limits of the first sink to ask for a chat apply.
+///
+/// Other replicas do not see it; the chat's [lease](crate::lease), held for
+/// all of the chat's sinks at once, keeps them from sending to the chat at
+/// the same time.
+pub fn for_chat(chat_id: &str, per_minute: u32, burst: u32) -> Arc<TokenBucket> {
+    CHAT_BUCKETS
+        .lock()
+        .unwrap()
+        .entry(chat_id.to_string())
+        .or_insert_with(|| Arc::new(TokenBucket::new(per_minute, burst)))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_free_then_refills_at_rate() {
+        let bucket = TokenBucket::new(60, 2);
+        let start = Instant::now();
+
+        bucket.acquire().await;
+        bucket.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        bucket.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_holds_back_callers() {
+        let bucket = TokenBucket::new(60, 5);
+        let start = Instant::now();
+
+        bucket.pause(Duration::from_secs(3));
+        bucket.acquire().await;
+
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+}
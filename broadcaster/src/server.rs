@@ -0,0 +1,138 @@
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use todo_events::nats;
+
+use crate::metrics::Metrics;
+
+/// Something that knows whether the NATS connection is up.
+pub trait ConnectionState: Send + Sync {
+    fn is_connected(&self) -> bool;
+
+    /// Whether the connection is gone for good rather than reconnecting.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+impl ConnectionState for async_nats::Client {
+    fn is_connected(&self) -> bool {
+        self.connection_state() == async_nats::connection::State::Connected
+    }
+}
+
+/// A client from [`crate::connect_to_nats`] with its [`nats::Status`].
+impl ConnectionState for (async_nats::Client, nats::Status) {
+    fn is_connected(&self) -> bool {
+        !self.is_closed() && self.0.is_connected()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.1.is_closed() || self.1.gave_up()
+    }
+}
+
+/// What `/healthz` and `/readyz` report. `/livez` only shows the process
+/// is serving, so a NATS outage does not get the pod restarted.
+#[derive(Clone)]
+pub struct Health {
+    nats: Arc<dyn ConnectionState>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Health {
+    pub fn new(nats: impl ConnectionState + 'static) -> Self {
+        Self {
+            nats: Arc::new(nats),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Marks startup as finished, i.e. every sink is consuming.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Whether NATS is connected; `/livez` does not depend on it.
+    pub fn is_nats_connected(&self) -> bool {
+        self.nats.is_connected()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst) && self.is_nats_connected()
+    }
+}
+
+struct AppState {
+    health: Health,
+    metrics: Metrics,
+}
+
+fn nats_state(health: &Health) -> &'static str {
+    if health.nats.is_closed() {
+        "closed"
+    } else if health.is_nats_connected() {
+        "connected"
+    } else {
+        "disconnected"
+    }
+}
+
+async fn liveness_check() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+async fn health_check(state: web::Data<AppState>) -> HttpResponse {
+    let body = serde_json::json!({ "nats": nats_state(&state.health) });
+
+    if state.health.is_nats_connected() {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+async fn readiness_check(state: web::Data<AppState>) -> HttpResponse {
+    let body = serde_json::json!({
+        "nats": nats_state(&state.health),
+        "consuming": state.health.ready.load(Ordering::SeqCst),
+    });
+
+    if state.health.is_ready() {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+async fn metrics(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(state.metrics.render())
+}
+
+pub fn run(listener: TcpListener, health: Health, metrics_registry: Metrics) -> Result<Server, std::io::Error> {
+    let state = web::Data::new(AppState {
+        health,
+        metrics: metrics_registry,
+    });
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .route("/livez", web::get().to(liveness_check))
+            .route("/healthz", web::get().to(health_check))
+            .route("/readyz", web::get().to(readiness_check))
+            .route("/metrics", web::get().to(metrics))
+    })
+    .workers(1)
+    // main stops the server once the consumers have drained.
+    .disable_signals()
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// Tells the consumers to stop pulling and finish what they are sending.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            requested: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.requested.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once [`trigger`](Self::trigger) has been called.
+    pub async fn requested(&self) {
+        let mut receiver = self.requested.subscribe();
+        // The sender lives in `self`, so the channel cannot close.
+        let _ = receiver.wait_for(|requested| *requested).await;
+    }
+
+    /// Drives `work` to completion unless shutdown was requested more than
+    /// `grace` ago, in which case it is dropped and `None` is returned.
+    pub async fn within_grace<T>(&self, grace: Duration, work: impl Future<Output = T>) -> Option<T> {
+        tokio::select! {
+            result = work => Some(result),
+            _ = async {
+                self.requested().await;
+                tokio::time::sleep(grace).await;
+            } => None,
+        }
+    }
+}
+
+/// Resolves on SIGTERM or SIGINT.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+
+        tokio::select! {
+            _ = terminate.recv() => tracing::info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("Received Ctrl-C");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn in_flight_work_finishes_within_grace() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger();
+
+        let result = shutdown
+            .within_grace(Duration::from_secs(5), async {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                "sent"
+            })
+            .await;
+
+        assert_eq!(result, Some("sent"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn work_is_abandoned_after_grace() {
+        let shutdown = Shutdown::new();
+        let slow = shutdown.within_grace(Duration::from_secs(5), tokio::time::sleep(Duration::from_secs(60)));
+
+        let trigger = async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            shutdown.trigger();
+        };
+
+        let started = tokio::time::Instant::now();
+        let (result, ()) = tokio::join!(slow, trigger);
+
+        assert_eq!(result, None);
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+    }
+}
@@ -0,0 +1,552 @@
+//! Notification text, rendered from per-action Tera templates.
+//!
+//! A templates file is TOML:
+//!
+//! ```toml
+//! format = "html"                      # "plain" (default), "html" or "markdown_v2"
+//! link = "https://example.com/the-project"
+//! fallback = "Something happened to {{ todo.content }}"
+//!
+//! [actions]
+//! created = "<b>New todo</b>: {{ todo.content }}{% if link %} ({{ link }}){% endif %}"
+//! ```
+//!
+//! Templates see `event` (the whole [`TodoEvent`]), `action`, `todo`,
+//! `previous` (null unless the action has one) and `link`. A todo's `due_at`
+//! and `tags` are only present when set, so guard them with `{% if %}`. The
+//! `digest` template, used by sinks in digest mode, sees `events`, `counts`
+//! (per `created`, `completed`, `updated`, `reopened`, `deleted`, `restored`,
+//! `purged`), a ready-made `summary` such as "5 todos created, 2 completed",
+//! and `link`. Values are escaped for `format`, so a todo's content cannot
+//! break the markup.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use tera::{Context, Tera};
+use todo_events::{Priority, TodoAction, TodoEvent, TodoSnapshot};
+
+use crate::config::ConfigError;
+
+const FALLBACK: &str = "fallback";
+const BUILTIN_FALLBACK: &str = "Unknown action on todo: \"{{ todo.content }}\"";
+const DIGEST: &str = "digest";
+const BUILTIN_DIGEST: &str = "{{ summary }}:\n\
+{% for event in events %}- {{ event.action }}: \"{{ event.todo.content }}\"\n{% endfor %}";
+
+const ACTIONS: [TodoAction; 6] = [
+    TodoAction::Created,
+    TodoAction::Updated,
+    TodoAction::Reopened,
+    TodoAction::Deleted,
+    TodoAction::Restored,
+    TodoAction::Purged,
+];
+
+/// Markup of rendered text, passed to Telegram as `parse_mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextFormat {
+    #[default]
+    Plain,
+    Html,
+    MarkdownV2,
+}
+
+impl TextFormat {
+    pub fn telegram_parse_mode(&self) -> Option<&'static str> {
+        match self {
+            Self::Plain => None,
+            Self::Html => Some("HTML"),
+            Self::MarkdownV2 => Some("MarkdownV2"),
+        }
+    }
+}
+
+/// Text of one notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub text: String,
+    pub format: TextFormat,
+}
+
+impl Rendered {
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            format: TextFormat::Plain,
+        }
+    }
+
+    /// The text as messages of at most `max_len` UTF-16 code units, the unit
+    /// Telegram measures its limit in. Messages break between lines, so the
+    /// markup of a line stays whole; only a line longer than `max_len` on its
+    /// own is cut.
+    pub fn split(&self, max_len: usize) -> Vec<Rendered> {
+        let max_len = max_len.max(1);
+        let mut parts = Vec::new();
+        let mut current = String::new();
+        let mut current_len = 0;
+
+        for line in self.text.split_inclusive('\n') {
+            let line_len = utf16_len(line);
+
+            if current_len + line_len > max_len && !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+
+            if line_len <= max_len {
+                current.push_str(line);
+                current_len += line_len;
+                continue;
+            }
+
+            for c in line.chars() {
+                if current_len + c.len_utf16() > max_len {
+                    parts.push(std::mem::take(&mut current));
+                    current_len = 0;
+                }
+                current.push(c);
+                current_len += c.len_utf16();
+            }
+        }
+        parts.push(current);
+
+        let parts: Vec<Rendered> = parts
+            .into_iter()
+            .map(|text| text.trim_end().to_string())
+            .filter(|text| !text.is_empty())
+            .map(|text| Rendered { text, format: self.format })
+            .collect();
+
+        if parts.is_empty() {
+            vec![self.clone()]
+        } else {
+            parts
+        }
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplatesFile {
+    #[serde(default)]
+    format: TextFormat,
+    link: Option<String>,
+    fallback: Option<String>,
+    digest: Option<String>,
+    #[serde(default)]
+    actions: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Templates {
+    tera: Tera,
+    format: TextFormat,
+    link: Option<String>,
+}
+
+impl Templates {
+    /// The messages broadcaster has always sent.
+    pub fn builtin() -> Self {
+        let file = TemplatesFile {
+            format: TextFormat::Plain,
+            link: None,
+            fallback: None,
+            digest: None,
+            actions: HashMap::from([
+                ("created".to_string(), "A todo was created: \"{{ todo.content }}\"".to_string()),
+                (
+                    "updated".to_string(),
+                    "A todo was updated: \"{{ todo.content }}\" (done: {{ todo.done }})".to_string(),
+                ),
+                ("reopened".to_string(), "A todo was reopened: \"{{ todo.content }}\"".to_string()),
+                ("deleted".to_string(), "A todo was deleted: \"{{ todo.content }}\"".to_string()),
+                ("restored".to_string(), "A todo was restored: \"{{ todo.content }}\"".to_string()),
+                (
+                    "purged".to_string(),
+                    "A todo was permanently deleted: \"{{ todo.content }}\"".to_string(),
+                ),
+            ]),
+        };
+
+        Self::from_file(file).expect("built-in templates are valid")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        Self::parse(&text)
+    }
+
+    /// Parses and validates a templates file: every template must compile and
+    /// render against a sample event of its action.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        Self::from_file(toml::from_str(text)?)
+    }
+
+    fn from_file(mut file: TemplatesFile) -> Result<Self, ConfigError> {
+        let invalid = |name: &str, e: tera::Error| {
+            ConfigError::Invalid(format!("template '{}': {}", name, error_chain(&e)))
+        };
+
+        if let Some(name) = file
+            .actions
+            .keys()
+            .find(|name| !ACTIONS.iter().any(|action| action.as_str() == name.as_str()))
+        {
+            return Err(ConfigError::Invalid(format!("unknown action '{}' in templates", name)));
+        }
+
+        let fallback = file.fallback.take().unwrap_or_else(|| BUILTIN_FALLBACK.to_string());
+        let digest = file.digest.take().unwrap_or_else(|| BUILTIN_DIGEST.to_string());
+
+        let mut tera = Tera::default();
+
+        match file.format {
+            TextFormat::Plain => tera.autoescape_on(vec![]),
+            TextFormat::Html => {
+                tera.autoescape_on(vec![""]);
+                tera.set_escape_fn(escape_html);
+            }
+            TextFormat::MarkdownV2 => {
+                tera.autoescape_on(vec![""]);
+                tera.set_escape_fn(escape_markdown_v2);
+            }
+        }
+
+        tera.add_raw_template(FALLBACK, &fallback).map_err(|e| invalid(FALLBACK, e))?;
+        tera.add_raw_template(DIGEST, &digest).map_err(|e| invalid(DIGEST, e))?;
+
+        for (name, template) in &file.actions {
+            tera.add_raw_template(name, template).map_err(|e| invalid(name, e))?;
+        }
+
+        let templates = Self {
+            tera,
+            format: file.format,
+            link: file.link,
+        };
+
+        for action in ACTIONS.into_iter().chain([TodoAction::Unknown]) {
+            templates
+                .render(&sample_event(action))
+                .map_err(|e| invalid(templates.template_name(action), e))?;
+        }
+
+        let samples: Vec<TodoEvent> = ACTIONS.into_iter().map(sample_event).collect();
+        templates.render_digest(&samples).map_err(|e| invalid(DIGEST, e))?;
+
+        Ok(templates)
+    }
+
+    fn template_name(&self, action: TodoAction) -> &'static str {
+        let name = action.as_str();
+        if action != TodoAction::Unknown && self.tera.get_template(name).is_ok() {
+            name
+        } else {
+            FALLBACK
+        }
+    }
+
+    pub fn render(&self, event: &TodoEvent) -> Result<Rendered, tera::Error> {
+        let mut context = Context::new();
+        context.insert("event", event);
+        context.insert("action", event.action.as_str());
+        context.insert("todo", &event.todo);
+        context.insert("previous", &event.previous);
+        context.insert("link", &self.link);
+
+        let text = self.tera.render(self.template_name(event.action), &context)?;
+
+        Ok(Rendered {
+            text,
+            format: self.format,
+        })
+    }
+
+    /// One message summarising `events`, oldest first.
+    pub fn render_digest(&self, events: &[TodoEvent]) -> Result<Rendered, tera::Error> {
+        let counts = DigestCounts::of(events);
+
+        let mut context = Context::new();
+        context.insert("events", events);
+        context.insert("counts", &counts);
+        context.insert("summary", &counts.summary());
+        context.insert("link", &self.link);
+
+        let text = self.tera.render(DIGEST, &context)?;
+
+        Ok(Rendered {
+            text: text.trim_end().to_string(),
+            format: self.format,
+        })
+    }
+}
+
+/// Events in a digest by what happened. An update that marks a todo done
+/// counts as `completed` rather than `updated`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct DigestCounts {
+    pub created: usize,
+    pub completed: usize,
+    pub updated: usize,
+    pub reopened: usize,
+    pub deleted: usize,
+    pub restored: usize,
+    pub purged: usize,
+    pub other: usize,
+}
+
+impl DigestCounts {
+    pub fn of(events: &[TodoEvent]) -> Self {
+        let mut counts = Self::default();
+
+        for event in events {
+            match event.action {
+                TodoAction::Created => counts.created += 1,
+                TodoAction::Updated => {
+                    let was_done = event.previous.as_ref().is_some_and(|previous| previous.done);
+                    if event.todo.done && !was_done {
+                        counts.completed += 1;
+                    } else {
+                        counts.updated += 1;
+                    }
+                }
+                TodoAction::Reopened => counts.reopened += 1,
+                TodoAction::Deleted => counts.deleted += 1,
+                TodoAction::Restored => counts.restored += 1,
+                TodoAction::Purged => counts.purged += 1,
+                TodoAction::Unknown => counts.other += 1,
+            }
+        }
+
+        counts
+    }
+
+    /// e.g. "5 todos created, 2 completed".
+    pub fn summary(&self) -> String {
+        let parts: Vec<(usize, &str)> = [
+            (self.created, "created"),
+            (self.completed, "completed"),
+            (self.updated, "updated"),
+            (self.reopened, "reopened"),
+            (self.deleted, "deleted"),
+            (self.restored, "restored"),
+            (self.purged, "purged"),
+            (self.other, "changed otherwise"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .collect();
+
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, (count, verb))| match (i, count) {
+                (0, 1) => format!("1 todo {}", verb),
+                (0, _) => format!("{} todos {}", count, verb),
+                _ => format!("{} {}", count, verb),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn error_chain(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+
+    message
+}
+
+fn sample_event(action: TodoAction) -> TodoEvent {
+    let at = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+    let todo = TodoSnapshot {
+        id: 1,
+        content: "Sample todo".to_string(),
+        done: false,
+        created_at: at,
+        updated_at: at,
+        completed_at: None,
+        version: 2,
+        priority: Priority::Normal,
+        due_at: None,
+        tags: Vec::new(),
+        list_id: None,
+        deleted_at: None,
+    };
+    let previous = matches!(
+        action,
+        TodoAction::Updated | TodoAction::Reopened | TodoAction::Deleted | TodoAction::Restored
+    )
+    .then(|| TodoSnapshot {
+        version: 1,
+        ..todo.clone()
+    });
+
+    TodoEvent::new("broadcaster", action, todo, previous)
+}
+
+fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_markdown_v2(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_templates_match_the_original_messages() {
+        let templates = Templates::builtin();
+        let mut event = sample_event(TodoAction::Updated);
+        event.todo.done = true;
+
+        assert_eq!(
+            templates.render(&event).unwrap(),
+            Rendered::plain("A todo was updated: \"Sample todo\" (done: true)")
+        );
+
+        event.action = TodoAction::Unknown;
+        assert_eq!(templates.render(&event).unwrap().text, "Unknown action on todo: \"Sample todo\"");
+    }
+
+    #[test]
+    fn values_are_escaped_for_the_format() {
+        let templates = Templates::parse(
+            r#"
+            format = "html"
+            [actions]
+            created = "<b>New:</b> {{ todo.content }}"
+            "#,
+        )
+        .unwrap();
+        let mut event = sample_event(TodoAction::Created);
+        event.todo.content = "a < b & c".to_string();
+
+        let rendered = templates.render(&event).unwrap();
+
+        assert_eq!(rendered.text, "<b>New:</b> a &lt; b &amp; c");
+        assert_eq!(rendered.format, TextFormat::Html);
+        assert_eq!(escape_markdown_v2("1.5*2"), "1\\.5\\*2");
+    }
+
+    #[test]
+    fn missing_actions_use_the_fallback() {
+        let templates = Templates::parse(
+            r#"
+            link = "https://example.com/the-project"
+            fallback = "{{ action }}: {{ todo.content }} {{ link }}"
+            "#,
+        )
+        .unwrap();
+
+        let rendered = templates.render(&sample_event(TodoAction::Deleted)).unwrap();
+
+        assert_eq!(rendered.text, "deleted: Sample todo https://example.com/the-project");
+    }
+
+    #[test]
+    fn digest_summarises_events_by_outcome() {
+        let created = sample_event(TodoAction::Created);
+        let mut completed = sample_event(TodoAction::Updated);
+        completed.todo.done = true;
+        let renamed = sample_event(TodoAction::Updated);
+        let purged = sample_event(TodoAction::Purged);
+
+        let events = [created.clone(), created, completed, renamed, purged];
+        let rendered = Templates::builtin().render_digest(&events).unwrap();
+
+        assert_eq!(
+            rendered.text,
+            "2 todos created, 1 completed, 1 updated, 1 purged:\n\
+             - created: \"Sample todo\"\n\
+             - created: \"Sample todo\"\n\
+             - updated: \"Sample todo\"\n\
+             - updated: \"Sample todo\"\n\
+             - purged: \"Sample todo\""
+        );
+        assert_eq!(DigestCounts::of(&events[..1]).summary(), "1 todo created");
+    }
+
+    #[test]
+    fn long_text_is_split_between_lines() {
+        let message = Rendered {
+            text: "<b>one</b>\n<b>two</b>\n<b>three</b>".to_string(),
+            format: TextFormat::Html,
+        };
+
+        let texts: Vec<String> = message.split(22).into_iter().map(|part| part.text).collect();
+        assert_eq!(texts, ["<b>one</b>\n<b>two</b>", "<b>three</b>"]);
+        assert!(message.split(22).iter().all(|part| part.format == TextFormat::Html));
+
+        assert_eq!(message.split(4096), std::slice::from_ref(&message));
+        assert_eq!(Rendered::plain("").split(10), [Rendered::plain("")]);
+    }
+
+    #[test]
+    fn lines_longer_than_the_limit_are_cut() {
+        let texts: Vec<String> = Rendered::plain("abcdefg\nhi").split(3).into_iter().map(|part| part.text).collect();
+        assert_eq!(texts, ["abc", "def", "g", "hi"]);
+
+        // 📋 is two UTF-16 code units, as Telegram counts it.
+        let texts: Vec<String> = Rendered::plain("📋📋📋").split(5).into_iter().map(|part| part.text).collect();
+        assert_eq!(texts, ["📋📋", "📋"]);
+    }
+
+    #[test]
+    fn deployed_templates_are_valid() {
+        Templates::parse(include_str!("../manifests/templates.toml")).unwrap();
+
+        let discord = Templates::parse(include_str!("../manifests/discord-templates.toml")).unwrap();
+        let events: Vec<TodoEvent> = ACTIONS.into_iter().map(sample_event).collect();
+        for rendered in events.iter().map(|event| discord.render(event)).chain([discord.render_digest(&events)]) {
+            let rendered = rendered.unwrap();
+            assert_eq!(rendered.format, TextFormat::Plain);
+            assert!(!rendered.text.contains('<'), "{}", rendered.text);
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let syntax = "[actions]\ncreated = \"{{ todo.content \"";
+        let unknown_variable = "[actions]\ncreated = \"{{ todo.title }}\"";
+        let unknown_action = "[actions]\narchived = \"{{ todo.content }}\"";
+
+        for text in [syntax, unknown_variable, unknown_action] {
+            assert!(matches!(Templates::parse(text), Err(ConfigError::Invalid(_))), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use broadcaster::server::{self, ConnectionState};
+use broadcaster::{Health, Metrics};
+
+#[derive(Clone, Default)]
+struct FakeConnection {
+    connected: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ConnectionState for FakeConnection {
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+struct TestApp {
+    address: String,
+    connection: FakeConnection,
+    health: Health,
+    metrics: Metrics,
+}
+
+fn spawn_app() -> TestApp {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+
+    let connection = FakeConnection::default();
+    let health = Health::new(connection.clone());
+    let metrics = Metrics::new();
+
+    let server = server::run(listener, health.clone(), metrics.clone()).expect("Failed to bind address");
+    tokio::spawn(server);
+
+    TestApp {
+        address: format!("http://127.0.0.1:{}", port),
+        connection,
+        health,
+        metrics,
+    }
+}
+
+#[tokio::test]
+async fn healthz_reflects_the_nats_connection() {
+    let app = spawn_app();
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/healthz", app.address)).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 503);
+
+    app.connection.connected.store(true, Ordering::SeqCst);
+
+    let response = client.get(format!("{}/healthz", app.address)).send().await.unwrap();
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["nats"], "connected");
+}
+
+#[tokio::test]
+async fn healthz_fails_once_the_connection_is_closed() {
+    let app = spawn_app();
+    app.connection.closed.store(true, Ordering::SeqCst);
+
+    let response = reqwest::get(format!("{}/healthz", app.address)).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["nats"], "closed");
+}
+
+#[tokio::test]
+async fn livez_ignores_the_nats_connection() {
+    let app = spawn_app();
+
+    let response = reqwest::get(format!("{}/livez", app.address)).await.unwrap();
+    assert!(response.status().is_success());
+    assert!(!app.connection.is_connected());
+}
+
+#[tokio::test]
+async fn readyz_waits_for_consumers() {
+    let app = spawn_app();
+    let client = reqwest::Client::new();
+    app.connection.connected.store(true, Ordering::SeqCst);
+
+    let response = client.get(format!("{}/readyz", app.address)).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 503);
+
+    app.health.set_ready(true);
+
+    let response = client.get(format!("{}/readyz", app.address)).send().await.unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_prometheus_format() {
+    let app = spawn_app();
+    app.metrics.sink("telegram").received.inc();
+
+    let response = reqwest::get(format!("{}/metrics", app.address)).await.unwrap();
+
+    assert!(response.status().is_success());
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains("broadcaster_events_received_total{sink=\"telegram\"} 1"));
+}
@@ -0,0 +1,392 @@
+//! Runs against a local nats-server with JetStream enabled, e.g.
+//! `nats-server -js`, then `cargo test -- --ignored`. Set `NATS_URL` to use
+//! another server.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_nats::jetstream;
+use broadcaster::config::{DigestConfig, FilterConfig};
+use broadcaster::consumer::{self, ConsumerConfig, DEAD_LETTER_REASON_HEADER, DELIVERY_COUNT_HEADER};
+use broadcaster::{lease, Filter, Metrics, Shutdown};
+use chrono::Utc;
+use todo_events::{Priority, TodoAction, TodoEvent, TodoSnapshot};
+use uuid::Uuid;
+
+async fn connect() -> jetstream::Context {
+    let url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string());
+    let client = async_nats::connect(url).await.expect("Failed to connect to nats-server");
+    jetstream::new(client)
+}
+
+fn test_config() -> ConsumerConfig {
+    ConsumerConfig {
+        durable_name: format!("broadcaster-test-{}", Uuid::new_v4().simple()),
+        max_deliver: 2,
+        ack_wait: Duration::from_secs(2),
+        shutdown_timeout: Duration::from_secs(5),
+    }
+}
+
+fn event(content: &str) -> TodoEvent {
+    event_with_action(content, TodoAction::Created)
+}
+
+fn event_with_action(content: &str, action: TodoAction) -> TodoEvent {
+    let now = Utc::now();
+    let todo = TodoSnapshot {
+        id: 1,
+        content: content.to_string(),
+        done: false,
+        created_at: now,
+        updated_at: now,
+        completed_at: None,
+        version: 1,
+        priority: Priority::Normal,
+        due_at: None,
+        tags: Vec::new(),
+        list_id: None,
+        deleted_at: None,
+    };
+    TodoEvent::new("broadcaster-test", action, todo, None)
+}
+
+async fn publish(jetstream: &jetstream::Context, event: &TodoEvent) {
+    jetstream
+        .publish(todo_events::SUBJECT, serde_json::to_vec(event).unwrap().into())
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+}
+
+/// Runs the consumer in the background, counting how often `event_id` is
+/// handled. Other events in the stream always succeed.
+fn spawn_consumer(
+    jetstream: &jetstream::Context,
+    consumer: jetstream::consumer::PullConsumer,
+    config: &ConsumerConfig,
+    event_id: Uuid,
+    succeed: bool,
+) -> Arc<AtomicUsize> {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let jetstream = jetstream.clone();
+    let config = config.clone();
+    let counter = attempts.clone();
+
+    tokio::spawn(async move {
+        let metrics = Metrics::new().sink("test");
+        let _ = consumer::run(&jetstream, &consumer, &config, &metrics, &Shutdown::new(), &Filter::default(), |event| {
+            let counter = counter.clone();
+            async move {
+                if event.event_id != event_id {
+                    return Ok(());
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                if succeed {
+                    Ok(())
+                } else {
+                    Err("sink unavailable".into())
+                }
+            }
+        })
+        .await;
+    });
+
+    attempts
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn acked_events_are_not_redelivered() {
+    let jetstream = connect().await;
+    let config = test_config();
+    let consumer = consumer::create_consumer(&jetstream, &config).await.unwrap();
+
+    let event = event("Delivered once");
+    let attempts = spawn_consumer(&jetstream, consumer, &config, event.event_id, true);
+    publish(&jetstream, &event).await;
+
+    tokio::time::sleep(config.ack_wait * 3).await;
+
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    stream.delete_consumer(&config.durable_name).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn failing_events_are_dead_lettered_after_max_deliver() {
+    let jetstream = connect().await;
+    let config = test_config();
+    let consumer = consumer::create_consumer(&jetstream, &config).await.unwrap();
+
+    let event = event("Never delivered");
+    let attempts = spawn_consumer(&jetstream, consumer, &config, event.event_id, false);
+    publish(&jetstream, &event).await;
+
+    let dead_letters = jetstream.get_stream(todo_events::DEAD_LETTER_STREAM).await.unwrap();
+    let mut dead_lettered = None;
+
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let Ok(message) = dead_letters
+            .get_last_raw_message_by_subject(todo_events::DEAD_LETTER_SUBJECT)
+            .await
+        else {
+            continue;
+        };
+        let payload: TodoEvent = serde_json::from_slice(&message.payload).unwrap();
+        if payload.event_id == event.event_id {
+            dead_lettered = Some(message);
+            break;
+        }
+    }
+
+    let message = dead_lettered.expect("event was not dead-lettered");
+    assert_eq!(attempts.load(Ordering::SeqCst), config.max_deliver as usize);
+    assert_eq!(
+        message.headers.get(DELIVERY_COUNT_HEADER).map(|value| value.as_str()),
+        Some("2")
+    );
+    assert_eq!(
+        message.headers.get(DEAD_LETTER_REASON_HEADER).map(|value| value.as_str()),
+        Some("sink unavailable")
+    );
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    stream.delete_consumer(&config.durable_name).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn digest_mode_batches_events_and_bypasses_immediate_actions() {
+    let jetstream = connect().await;
+    let config = test_config();
+    let consumer = consumer::create_consumer(&jetstream, &config).await.unwrap();
+    let digest = DigestConfig {
+        window_secs: 60,
+        max_events: 3,
+        immediate: vec![TodoAction::Deleted],
+    };
+
+    let run_id = Uuid::new_v4().to_string();
+    let singles = Arc::new(Mutex::new(Vec::new()));
+    let digests = Arc::new(Mutex::new(Vec::new()));
+
+    {
+        let jetstream = jetstream.clone();
+        let config = config.clone();
+        let (singles, digests, run_id) = (singles.clone(), digests.clone(), run_id.clone());
+
+        tokio::spawn(async move {
+            let _ = consumer::run_digest(
+                &jetstream,
+                &consumer,
+                &config,
+                &Metrics::new().sink("test"),
+                &Shutdown::new(),
+                &Filter::default(),
+                &digest,
+                |event| {
+                    let singles = singles.clone();
+                    async move {
+                        singles.lock().unwrap().push(event.todo.content);
+                        Ok(())
+                    }
+                },
+                |events| {
+                    let (digests, run_id) = (digests.clone(), run_id.clone());
+                    async move {
+                        let ours: Vec<String> = events
+                            .into_iter()
+                            .map(|event| event.todo.content)
+                            .filter(|content| content.starts_with(&run_id))
+                            .collect();
+                        digests.lock().unwrap().push(ours);
+                        Ok(())
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    for (n, action) in [TodoAction::Created, TodoAction::Deleted, TodoAction::Created, TodoAction::Created]
+        .into_iter()
+        .enumerate()
+    {
+        publish(&jetstream, &event_with_action(&format!("{} {}", run_id, n), action)).await;
+    }
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let ours = |content: &String| content.starts_with(&run_id);
+    assert_eq!(
+        singles.lock().unwrap().iter().filter(|content| ours(content)).collect::<Vec<_>>(),
+        [&format!("{} 1", run_id)]
+    );
+    assert_eq!(
+        digests.lock().unwrap().as_slice(),
+        [vec![format!("{} 0", run_id), format!("{} 2", run_id), format!("{} 3", run_id)]]
+    );
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    stream.delete_consumer(&config.durable_name).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn filtered_events_are_acked_without_joining_a_digest() {
+    let jetstream = connect().await;
+    let config = test_config();
+    let consumer = consumer::create_consumer(&jetstream, &config).await.unwrap();
+    let digest = DigestConfig {
+        window_secs: 60,
+        max_events: 2,
+        immediate: Vec::new(),
+    };
+
+    let run_id = Uuid::new_v4().to_string();
+    let filter = Filter::from_config(&FilterConfig {
+        content: Some(format!("^{} ", run_id)),
+        actions: vec![TodoAction::Created],
+        quiet_hours: None,
+    })
+    .unwrap();
+    let metrics = Metrics::new().sink("test");
+    let digests = Arc::new(Mutex::new(Vec::new()));
+
+    {
+        let jetstream = jetstream.clone();
+        let config = config.clone();
+        let (metrics, digests) = (metrics.clone(), digests.clone());
+
+        tokio::spawn(async move {
+            let _ = consumer::run_digest(
+                &jetstream,
+                &consumer,
+                &config,
+                &metrics,
+                &Shutdown::new(),
+                &filter,
+                &digest,
+                |_| async { Ok(()) },
+                |events| {
+                    let digests = digests.clone();
+                    async move {
+                        digests
+                            .lock()
+                            .unwrap()
+                            .push(events.into_iter().map(|event| event.todo.content).collect::<Vec<_>>());
+                        Ok(())
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    for (n, action) in [TodoAction::Created, TodoAction::Reopened, TodoAction::Updated, TodoAction::Created]
+        .into_iter()
+        .enumerate()
+    {
+        publish(&jetstream, &event_with_action(&format!("{} {}", run_id, n), action)).await;
+    }
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // The two filtered events neither filled the digest early nor were sent.
+    assert_eq!(
+        digests.lock().unwrap().as_slice(),
+        [vec![format!("{} 0", run_id), format!("{} 3", run_id)]]
+    );
+    assert_eq!(metrics.filtered.get(), 2);
+    assert_eq!(metrics.delivered.get(), 2);
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    stream.delete_consumer(&config.durable_name).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn replicas_sharing_a_leased_digest_sink_send_one_digest_per_window() {
+    let jetstream = connect().await;
+    let config = test_config();
+    let store = lease::bucket(&jetstream).await.unwrap();
+    let key = format!("digest-{}", config.durable_name);
+    let digest = DigestConfig {
+        window_secs: 2,
+        max_events: 50,
+        immediate: Vec::new(),
+    };
+
+    let run_id = Uuid::new_v4().to_string();
+    let digests = Arc::new(Mutex::new(Vec::new()));
+    let shutdown = Shutdown::new();
+
+    for replica in 0..3 {
+        let consumer = consumer::create_consumer(&jetstream, &config).await.unwrap();
+        let (jetstream, config, store, key, digest) =
+            (jetstream.clone(), config.clone(), store.clone(), key.clone(), digest.clone());
+        let (digests, run_id, shutdown) = (digests.clone(), run_id.clone(), shutdown.clone());
+
+        tokio::spawn(async move {
+            let holder = format!("replica-{}", replica);
+            let _ = lease::run_exclusively(&store, &key, &holder, &shutdown, |stop| {
+                let (jetstream, consumer, config, digest) =
+                    (jetstream.clone(), consumer.clone(), config.clone(), digest.clone());
+                let (digests, run_id) = (digests.clone(), run_id.clone());
+
+                async move {
+                    consumer::run_digest(
+                        &jetstream,
+                        &consumer,
+                        &config,
+                        &Metrics::new().sink("test"),
+                        &stop,
+                        &Filter::default(),
+                        &digest,
+                        |_| async { Ok(()) },
+                        |events| {
+                            let (digests, run_id) = (digests.clone(), run_id.clone());
+                            async move {
+                                let ours: Vec<String> = events
+                                    .into_iter()
+                                    .map(|event| event.todo.content)
+                                    .filter(|content| content.starts_with(&run_id))
+                                    .collect();
+                                if !ours.is_empty() {
+                                    digests.lock().unwrap().push(ours);
+                                }
+                                Ok(())
+                            }
+                        },
+                    )
+                    .await
+                }
+            })
+            .await;
+        });
+    }
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let contents: Vec<String> = (0..5).map(|n| format!("{} {}", run_id, n)).collect();
+    for content in &contents {
+        publish(&jetstream, &event(content)).await;
+    }
+
+    tokio::time::sleep(digest.window() + Duration::from_secs(3)).await;
+
+    assert_eq!(digests.lock().unwrap().as_slice(), [contents]);
+
+    shutdown.trigger();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    stream.delete_consumer(&config.durable_name).await.unwrap();
+}
@@ -0,0 +1,194 @@
+//! Runs the broadcaster binary against a local nats-server with JetStream
+//! enabled, e.g. `nats-server -js`, then `cargo test -- --ignored`. Set
+//! `NATS_URL` to use another server.
+
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use async_nats::jetstream::{self, consumer::pull};
+use chrono::Utc;
+use todo_events::{Priority, TodoAction, TodoEvent, TodoSnapshot};
+use tokio::process::{Child, Command};
+use uuid::Uuid;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn nats_url() -> String {
+    std::env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".to_string())
+}
+
+fn event(content: &str) -> TodoEvent {
+    let now = Utc::now();
+    let todo = TodoSnapshot {
+        id: 1,
+        content: content.to_string(),
+        done: false,
+        created_at: now,
+        updated_at: now,
+        completed_at: None,
+        version: 1,
+        priority: Priority::Normal,
+        due_at: None,
+        tags: Vec::new(),
+        list_id: None,
+        deleted_at: None,
+    };
+    TodoEvent::new("broadcaster-test", TodoAction::Created, todo, None)
+}
+
+/// A running broadcaster with one webhook sink named `slow`.
+struct Broadcaster {
+    child: Child,
+    durable_name: String,
+}
+
+/// Starts the binary and waits until its consumer exists, so events published
+/// afterwards reach it.
+async fn spawn_broadcaster(jetstream: &jetstream::Context, webhook_url: &str, shutdown_timeout_secs: u64) -> Broadcaster {
+    let prefix = format!("broadcaster-test-{}", Uuid::new_v4().simple());
+    let config_path = std::env::temp_dir().join(format!("{}.toml", prefix));
+    std::fs::write(
+        &config_path,
+        format!("[[sinks]]\nname = \"slow\"\ntype = \"webhook\"\nurl = \"{}\"\n", webhook_url),
+    )
+    .unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_broadcaster"))
+        .env("NATS_URL", nats_url())
+        .env("PORT", "0")
+        .env("BROADCASTER_CONFIG", &config_path)
+        .env("BROADCASTER_DURABLE_NAME", &prefix)
+        .env("BROADCASTER_SHUTDOWN_TIMEOUT_SECS", shutdown_timeout_secs.to_string())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Failed to start broadcaster");
+
+    let durable_name = format!("{}-slow", prefix);
+    let stream = todo_events::jetstream::get_or_create_stream(jetstream).await.unwrap();
+
+    for _ in 0..50 {
+        if stream.get_consumer::<pull::Config>(&durable_name).await.is_ok() {
+            // Give the binary a moment to issue its first pull.
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            return Broadcaster { child, durable_name };
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("broadcaster did not create its consumer");
+}
+
+async fn wait_for_request(server: &MockServer) {
+    for _ in 0..50 {
+        if !server.received_requests().await.unwrap_or_default().is_empty() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("webhook was never called");
+}
+
+async fn terminate(child: &Child) {
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().expect("broadcaster exited early").to_string()])
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn sigterm_waits_for_the_notification_in_flight() {
+    let client = async_nats::connect(nats_url()).await.unwrap();
+    let jetstream = jetstream::new(client);
+
+    let webhook = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(3)))
+        .mount(&webhook)
+        .await;
+
+    let mut broadcaster = spawn_broadcaster(&jetstream, &webhook.uri(), 20).await;
+
+    jetstream
+        .publish(todo_events::SUBJECT, serde_json::to_vec(&event("Slow send")).unwrap().into())
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    wait_for_request(&webhook).await;
+    let signalled = Instant::now();
+    terminate(&broadcaster.child).await;
+
+    let status = tokio::time::timeout(Duration::from_secs(15), broadcaster.child.wait())
+        .await
+        .expect("broadcaster did not exit")
+        .unwrap();
+
+    assert!(status.success());
+    assert!(signalled.elapsed() >= Duration::from_secs(2), "exited before the send finished");
+
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    let mut consumer = stream.get_consumer::<pull::Config>(&broadcaster.durable_name).await.unwrap();
+    let info = consumer.info().await.unwrap();
+    assert_eq!(info.num_ack_pending, 0);
+    assert_eq!(info.num_pending, 0);
+
+    stream.delete_consumer(&broadcaster.durable_name).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local nats-server with JetStream"]
+async fn sigterm_hands_back_sends_that_miss_the_deadline() {
+    let client = async_nats::connect(nats_url()).await.unwrap();
+    let jetstream = jetstream::new(client);
+
+    let webhook = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+        .mount(&webhook)
+        .await;
+
+    let mut broadcaster = spawn_broadcaster(&jetstream, &webhook.uri(), 1).await;
+
+    jetstream
+        .publish(todo_events::SUBJECT, serde_json::to_vec(&event("Stuck send")).unwrap().into())
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    wait_for_request(&webhook).await;
+    let signalled = Instant::now();
+    terminate(&broadcaster.child).await;
+
+    let status = tokio::time::timeout(Duration::from_secs(15), broadcaster.child.wait())
+        .await
+        .expect("broadcaster did not exit")
+        .unwrap();
+
+    assert!(status.success());
+    assert!(signalled.elapsed() < Duration::from_secs(10), "waited past the deadline");
+
+    // The nacked message is redelivered to the next consumer right away.
+    let stream = jetstream.get_stream(todo_events::STREAM).await.unwrap();
+    let consumer = stream.get_consumer::<pull::Config>(&broadcaster.durable_name).await.unwrap();
+    let mut messages = consumer
+        .batch()
+        .max_messages(1)
+        .expires(Duration::from_secs(5))
+        .messages()
+        .await
+        .unwrap();
+    let redelivered = futures::StreamExt::next(&mut messages)
+        .await
+        .expect("message was not handed back")
+        .unwrap();
+    assert_eq!(redelivered.info().unwrap().delivered, 2);
+    redelivered.ack().await.unwrap();
+
+    stream.delete_consumer(&broadcaster.durable_name).await.unwrap();
+}
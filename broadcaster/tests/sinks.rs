@@ -0,0 +1,407 @@
+//! Each sink against a local mock server.
+
+use std::time::{Duration, Instant};
+
+use broadcaster::config::{SmtpConfig, SmtpTls};
+use broadcaster::templates::{Rendered, Templates, TextFormat};
+use broadcaster::notifier::{ChatFlavor, ChatWebhook, Notifier, NotifyError, Smtp, Telegram, Webhook, SIGNATURE_HEADER};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use todo_events::{Priority, TodoAction, TodoEvent, TodoSnapshot};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use wiremock::matchers::{body_json, header_exists, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn event() -> TodoEvent {
+    let now = Utc::now();
+    let todo = TodoSnapshot {
+        id: 3,
+        content: "Water the plants".to_string(),
+        done: false,
+        created_at: now,
+        updated_at: now,
+        completed_at: None,
+        version: 1,
+        priority: Priority::Normal,
+        due_at: None,
+        tags: Vec::new(),
+        list_id: None,
+        deleted_at: None,
+    };
+    TodoEvent::new("broadcaster-test", TodoAction::Created, todo, None)
+}
+
+#[tokio::test]
+async fn telegram_posts_send_message() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/bot123:abc/sendMessage"))
+        .and(body_json(serde_json::json!({ "chat_id": "42", "text": "hello" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "42".to_string()).with_api_url(server.uri());
+
+    telegram.notify(&event(), &Rendered::plain("hello")).await.unwrap();
+}
+
+#[tokio::test]
+async fn telegram_sends_the_parse_mode() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(body_json(serde_json::json!({ "chat_id": "html-chat", "text": "<b>hi</b>", "parse_mode": "HTML" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "html-chat".to_string()).with_api_url(server.uri());
+    let message = Rendered {
+        text: "<b>hi</b>".to_string(),
+        format: TextFormat::Html,
+    };
+
+    telegram.notify(&event(), &message).await.unwrap();
+}
+
+#[tokio::test]
+async fn telegram_retries_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "retry-chat".to_string()).with_api_url(server.uri());
+
+    telegram.notify(&event(), &Rendered::plain("hello")).await.unwrap();
+}
+
+#[tokio::test]
+async fn telegram_honours_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+            "ok": false,
+            "error_code": 429,
+            "description": "Too Many Requests: retry after 1",
+            "parameters": { "retry_after": 1 }
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "throttled-chat".to_string()).with_api_url(server.uri());
+    let start = Instant::now();
+
+    telegram.notify(&event(), &Rendered::plain("hello")).await.unwrap();
+
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn telegram_does_not_retry_client_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: chat not found"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "missing-chat".to_string()).with_api_url(server.uri());
+
+    match telegram.notify(&event(), &Rendered::plain("hello")).await {
+        Err(NotifyError::Status { status, body }) => {
+            assert_eq!(status.as_u16(), 400);
+            assert_eq!(body, "Bad Request: chat not found");
+        }
+        other => panic!("expected a status error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn telegram_hands_long_retry_after_back_to_the_consumer() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+            "ok": false,
+            "parameters": { "retry_after": 120 }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "flooded-chat".to_string()).with_api_url(server.uri());
+
+    let error = telegram.notify(&event(), &Rendered::plain("hello")).await.unwrap_err();
+
+    assert_eq!(error.retry_after(), Some(Duration::from_secs(120)));
+}
+
+/// A digest as long as one can get by default: `digest.max_events` (50)
+/// events, each with the longest content a todo may have (140 characters).
+fn full_digest(templates: &str) -> Rendered {
+    let events: Vec<TodoEvent> = (0..50)
+        .map(|i| {
+            let mut event = event();
+            event.todo.content = format!("{:03} {}&", i, "x".repeat(135));
+            event
+        })
+        .collect();
+
+    Templates::parse(templates).unwrap().render_digest(&events).unwrap()
+}
+
+/// Texts posted to `server`, read from each request's `field`.
+async fn posted_texts(server: &MockServer, field: &str) -> Vec<String> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body[field].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn telegram_splits_digests_longer_than_a_message() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true })))
+        .mount(&server)
+        .await;
+
+    let telegram = Telegram::new("123:abc".to_string(), "digest-chat".to_string()).with_api_url(server.uri());
+    let digest = full_digest(include_str!("../manifests/templates.toml"));
+    assert!(digest.text.encode_utf16().count() > 4096);
+
+    telegram.notify_digest(&[], &digest).await.unwrap();
+
+    let texts = posted_texts(&server, "text").await;
+    assert!(texts.len() > 1);
+    assert!(texts.iter().all(|text| text.encode_utf16().count() <= 4096));
+    assert_eq!(texts.join("\n"), digest.text);
+}
+
+#[tokio::test]
+async fn webhook_signs_the_event_body() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .and(header_exists(SIGNATURE_HEADER))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let event = event();
+    let webhook = Webhook::new(format!("{}/hook", server.uri()), Some("s3cret".to_string()));
+
+    webhook.notify(&event, &Rendered::plain("ignored")).await.unwrap();
+
+    let request = &server.received_requests().await.unwrap()[0];
+    let received: TodoEvent = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(received, event);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(&request.body);
+    let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+    assert_eq!(request.headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(), expected);
+}
+
+#[tokio::test]
+async fn webhook_posts_digests_as_an_array() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let events = vec![event(), event()];
+    let webhook = Webhook::new(server.uri(), None);
+
+    webhook.notify_digest(&events, &Rendered::plain("ignored")).await.unwrap();
+
+    let request = &server.received_requests().await.unwrap()[0];
+    let received: Vec<TodoEvent> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(received, events);
+}
+
+#[tokio::test]
+async fn webhook_fails_on_error_status() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503).set_body_string("down"))
+        .mount(&server)
+        .await;
+
+    let webhook = Webhook::new(server.uri(), None);
+
+    match webhook.notify(&event(), &Rendered::plain("ignored")).await {
+        Err(NotifyError::Status { status, body }) => {
+            assert_eq!(status.as_u16(), 503);
+            assert_eq!(body, "down");
+        }
+        other => panic!("expected a status error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn http_sinks_give_up_on_endpoints_that_never_respond() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+        .mount(&server)
+        .await;
+
+    let timeout = Duration::from_millis(200);
+    let notifiers: [Box<dyn Notifier>; 2] = [
+        Box::new(Webhook::new(server.uri(), None).with_timeout(timeout)),
+        Box::new(ChatWebhook::new(ChatFlavor::Discord, server.uri()).with_timeout(timeout)),
+    ];
+
+    for notifier in notifiers {
+        let started = Instant::now();
+        let error = notifier.notify(&event(), &Rendered::plain("hello")).await.unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(&error, NotifyError::Http(e) if e.is_timeout()), "unexpected {:?}", error);
+        assert!(error.is_transient());
+    }
+}
+
+#[tokio::test]
+async fn chat_webhooks_use_their_text_field() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/slack"))
+        .and(body_json(serde_json::json!({ "text": "hello" })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/discord"))
+        .and(body_json(serde_json::json!({ "content": "hello" })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let slack = ChatWebhook::new(ChatFlavor::Slack, format!("{}/slack", server.uri()));
+    let discord = ChatWebhook::new(ChatFlavor::Discord, format!("{}/discord", server.uri()));
+
+    slack.notify(&event(), &Rendered::plain("hello")).await.unwrap();
+    discord.notify(&event(), &Rendered::plain("hello")).await.unwrap();
+}
+
+#[tokio::test]
+async fn discord_splits_digests_longer_than_a_message() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&server)
+        .await;
+
+    let discord = ChatWebhook::new(ChatFlavor::Discord, server.uri());
+    let digest = full_digest(include_str!("../manifests/discord-templates.toml"));
+
+    discord.notify_digest(&[], &digest).await.unwrap();
+
+    let texts = posted_texts(&server, "content").await;
+    assert!(texts.len() > 1);
+    assert!(texts.iter().all(|text| text.encode_utf16().count() <= 2000));
+    assert_eq!(texts.join("\n"), digest.text);
+}
+
+/// Accepts a single SMTP session and returns the commands and message data.
+async fn mock_smtp_server() -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let handle = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut received = Vec::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+
+        while let Some(line) = lines.next_line().await.unwrap() {
+            received.push(line.clone());
+
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 queued\r\n"
+            } else if line.starts_with("DATA") {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+
+            writer.write_all(reply).await.unwrap();
+        }
+
+        received
+    });
+
+    (port, handle)
+}
+
+#[tokio::test]
+async fn smtp_emails_every_recipient() {
+    let (port, server) = mock_smtp_server().await;
+
+    let smtp = Smtp::from_config(&SmtpConfig {
+        host: "127.0.0.1".to_string(),
+        port: Some(port),
+        tls: SmtpTls::None,
+        username: None,
+        password: None,
+        from: "Todos <todos@example.com>".to_string(),
+        to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+    })
+    .unwrap();
+
+    smtp.notify(&event(), &Rendered::plain("A todo was created")).await.unwrap();
+
+    let received = server.await.unwrap();
+    assert!(received.iter().any(|line| line == "MAIL FROM:<todos@example.com>"));
+    assert!(received.iter().any(|line| line == "RCPT TO:<a@example.com>"));
+    assert!(received.iter().any(|line| line == "RCPT TO:<b@example.com>"));
+    assert!(received.iter().any(|line| line == "Subject: Todo created: Water the plants"));
+    assert!(received.iter().any(|line| line == "A todo was created"));
+}
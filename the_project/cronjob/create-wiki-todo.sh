@@ -16,8 +16,14 @@ TODO_CONTENT="Read $WIKI_URL"
 
 echo "Creating todo: $TODO_CONTENT"
 
+# Retries send the same key, so the backend creates the todo only once.
+IDEMPOTENCY_KEY=$(cat /proc/sys/kernel/random/uuid)
+
 curl -X POST "${TODO_BACKEND_URL}/todos" \
+    --max-time 30 \
+    --retry 3 \
     -H "Content-Type: application/json" \
+    -H "Idempotency-Key: ${IDEMPOTENCY_KEY}" \
     -d "{\"content\":\"${TODO_CONTENT}\"}" \
     -v
 
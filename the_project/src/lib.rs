@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs;
 use std::net::TcpListener;
 use std::path::Path;
@@ -6,6 +7,7 @@ use std::time::SystemTime;
 
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpResponse, HttpServer};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone)]
@@ -13,11 +15,22 @@ struct Config {
     image_refresh_secs: u64,
     image_path: String,
     todo_backend_url: String,
+    todo_lists_url: String,
     image_timestamp_path: String,
 }
 
 impl Config {
     fn from_env() -> Self {
+        let todo_backend_url = std::env::var("TODO_BACKEND_URL")
+            .unwrap_or_else(|_| "http://todo-backend-svc:3000/todos".to_string());
+        // The lists live next to the todos unless configured otherwise.
+        let todo_lists_url = std::env::var("TODO_BACKEND_LISTS_URL").unwrap_or_else(|_| {
+            match todo_backend_url.strip_suffix("/todos") {
+                Some(base) => format!("{}/lists", base),
+                None => "http://todo-backend-svc:3000/lists".to_string(),
+            }
+        });
+
         Self {
             image_refresh_secs: std::env::var("IMAGE_REFRESH_SECS")
                 .unwrap_or_else(|_| "600".to_string())
@@ -25,8 +38,8 @@ impl Config {
                 .expect("IMAGE_REFRESH_SECS must be a valid number"),
             image_path: std::env::var("IMAGE_PATH")
                 .unwrap_or_else(|_| "/usr/src/app/cache/image.jpg".to_string()),
-            todo_backend_url: std::env::var("TODO_BACKEND_URL")
-                .unwrap_or_else(|_| "http://todo-backend-svc:3000/todos".to_string()),
+            todo_backend_url,
+            todo_lists_url,
             image_timestamp_path: std::env::var("IMAGE_TIMESTAMP_PATH")
                 .unwrap_or_else(|_| "/usr/src/app/cache/image_timestamp.txt".to_string()),
         }
@@ -37,14 +50,56 @@ impl Config {
 struct AppState {
     client: reqwest::Client,
     image_lock: Arc<Mutex<()>>,
+    /// Keyed by the URL the todos were fetched from.
+    todo_cache: Arc<Mutex<HashMap<String, CachedTodos>>>,
     config: Config,
 }
 
+/// Last todo list fetched from a backend URL, revalidated with `If-None-Match`.
+struct CachedTodos {
+    etag: String,
+    todos: Vec<Todo>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Todo {
     id: usize,
     content: String,
     done: bool,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    completed_at: Option<DateTime<Utc>>,
+    #[serde(default = "default_priority")]
+    priority: String,
+    #[serde(default)]
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Todo {
+    fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        !self.done && self.due_at.is_some_and(|due_at| due_at < now)
+    }
+}
+
+fn default_priority() -> String {
+    "normal".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TodoList {
+    id: i32,
+    name: String,
+    is_default: bool,
+    #[serde(default)]
+    open_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexParams {
+    list: Option<i32>,
 }
 
 async fn health_check() -> HttpResponse {
@@ -53,12 +108,31 @@ async fn health_check() -> HttpResponse {
         .body(r#"{"status":"ok"}"#)
 }
 
-async fn index(state: web::Data<AppState>) -> HttpResponse {
+async fn index(state: web::Data<AppState>, params: web::Query<IndexParams>) -> HttpResponse {
     if let Err(e) = ensure_image(&state.client, &state.image_lock, &state.config).await {
         tracing::error!("Failed to ensure image: {}", e);
     }
 
-    let todos = match fetch_todos(&state.client, &state.config).await {
+    let lists = match fetch_lists(&state.client, &state.config).await {
+        Ok(lists) => lists,
+        Err(e) => {
+            tracing::error!("Failed to fetch lists: {}", e);
+            Vec::new()
+        }
+    };
+
+    // An unknown list falls back to the default one.
+    let current_list = params
+        .list
+        .and_then(|id| lists.iter().find(|list| list.id == id))
+        .or_else(|| lists.iter().find(|list| list.is_default));
+
+    let todos_url = match current_list {
+        Some(list) if !list.is_default => format!("{}/{}/todos", state.config.todo_lists_url, list.id),
+        _ => state.config.todo_backend_url.clone(),
+    };
+
+    let todos = match fetch_todos(&state.client, &state.todo_cache, &todos_url).await {
         Ok(todos) => todos,
         Err(e) => {
             tracing::error!("Failed to fetch todos: {}", e);
@@ -66,23 +140,9 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
         }
     };
 
-    let todo_items_html: String = todos
-        .iter()
-        .map(|todo| {
-            let done_class = if todo.done { " done" } else { "" };
-            let done_button = if !todo.done {
-                format!(r#"<button class="done-button" onclick="markDone({})">Done</button>"#, todo.id)
-            } else {
-                String::from(r#"<span class="done-badge">✓ Done</span>"#)
-            };
-            format!(
-                r#"<li class="todo-item{}">{}{}</li>"#,
-                done_class,
-                html_escape(&todo.content),
-                done_button
-            )
-        })
-        .collect();
+    let now = Utc::now();
+    let todo_items_html: String = todos.iter().map(|todo| todo_item_html(todo, now)).collect();
+    let list_switcher_html = list_switcher_html(&lists, current_list);
 
     let html = format!(
         r#"<!DOCTYPE html>
@@ -106,6 +166,23 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
             .done-badge {{ color: #28a745; font-weight: bold; }}
             .char-counter {{ font-size: 12px; color: #666; margin-top: 5px; }}
             .char-counter.warning {{ color: #dc3545; }}
+            .todo-meta {{ font-size: 12px; color: #888; margin-top: 4px; text-decoration: none; display: block; }}
+            .todo-options {{ display: flex; gap: 10px; margin-bottom: 10px; font-size: 14px; color: #555; align-items: center; }}
+            .todo-options input, .todo-options select {{ padding: 6px; border: 1px solid #ddd; border-radius: 4px; }}
+            .todo-item.overdue {{ border-left-color: #dc3545; background-color: #fdecea; }}
+            .todo-item.priority-high .todo-content {{ font-weight: bold; }}
+            .todo-item.priority-low .todo-content {{ color: #777; }}
+            .priority-badge {{ font-size: 11px; padding: 1px 6px; border-radius: 8px; margin-left: 6px; background-color: #e9ecef; color: #555; text-decoration: none; }}
+            .priority-badge.high {{ background-color: #dc3545; color: white; }}
+            .overdue-label {{ color: #dc3545; font-weight: bold; }}
+            .tag {{ display: inline-block; font-size: 11px; padding: 1px 6px; margin-right: 4px; border-radius: 8px; background-color: #e3f2fd; color: #0d47a1; }}
+            .todo-actions {{ display: flex; gap: 6px; align-items: center; }}
+            .edit-button {{ padding: 6px 12px; background-color: #6c757d; color: white; border: none; border-radius: 4px; cursor: pointer; font-size: 14px; }}
+            .todo-edit {{ display: flex; gap: 6px; margin-top: 8px; font-size: 13px; text-decoration: none; }}
+            .todo-edit[hidden] {{ display: none; }}
+            .list-switcher {{ display: flex; gap: 10px; align-items: center; margin-bottom: 15px; }}
+            .list-switcher select {{ padding: 8px; font-size: 16px; border: 1px solid #ddd; border-radius: 4px; }}
+            .list-button {{ padding: 8px 14px; background-color: #6c757d; color: white; border: none; border-radius: 4px; cursor: pointer; }}
             .empty-state {{ text-align: center; padding: 40px; color: #999; }}
         </style>
     </head>
@@ -114,16 +191,27 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
 
         <div class="todo-section">
             <h2>Todo List</h2>
+            {list_switcher}
             <div class="todo-form">
                 <input type="text"
                        id="todoInput"
                        class="todo-input"
                        placeholder="Enter a new todo (max 140 characters)"
-                       maxlength="140"
                        oninput="updateCharCounter()">
                 <button class="todo-button" onclick="addTodo()">Send</button>
             </div>
             <div id="charCounter" class="char-counter">0 / 140 characters</div>
+            <div class="todo-options">
+                <label>Priority
+                    <select id="prioritySelect">
+                        <option value="low">Low</option>
+                        <option value="normal" selected>Normal</option>
+                        <option value="high">High</option>
+                    </select>
+                </label>
+                <label>Due <input type="datetime-local" id="dueInput"></label>
+                <input type="text" id="tagsInput" placeholder="Tags, comma-separated">
+            </div>
 
             <h3>Existing Todos:</h3>
             <ul class="todo-list" id="todoList">
@@ -139,10 +227,52 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
         </div>
 
         <script>
+            // Lists other than the default one take their todos at a nested route.
+            const currentListId = {current_list_id};
+            const todosUrl = currentListId === null ? '/todos' : `/lists/${{currentListId}}/todos`;
+
+            function switchList(id) {{
+                window.location.search = '?list=' + encodeURIComponent(id);
+            }}
+
+            async function createList() {{
+                const name = prompt('Name of the new list');
+                if (name === null || name.trim() === '') {{
+                    return;
+                }}
+
+                try {{
+                    const response = await fetch('/lists', {{
+                        method: 'POST',
+                        headers: {{
+                            'Content-Type': 'application/json',
+                        }},
+                        body: JSON.stringify({{ name: name }})
+                    }});
+
+                    if (!response.ok) {{
+                        const error = await response.json();
+                        alert('Error: ' + (error.detail || 'Failed to create list'));
+                        return;
+                    }}
+
+                    const list = await response.json();
+                    switchList(list.id);
+                }} catch (error) {{
+                    alert('Error creating list: ' + error.message);
+                }}
+            }}
+
+            // Counts characters the way the backend does: after NFC and
+            // whitespace normalization, by code point rather than UTF-16 unit.
+            function charCount(text) {{
+                return Array.from(text.normalize('NFC').trim().replace(/\s+/g, ' ')).length;
+            }}
+
             function updateCharCounter() {{
                 const input = document.getElementById('todoInput');
                 const counter = document.getElementById('charCounter');
-                const length = input.value.length;
+                const length = charCount(input.value);
                 counter.textContent = length + ' / 140 characters';
 
                 if (length > 130) {{
@@ -152,6 +282,33 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
                 }}
             }}
 
+            function parseTags(text) {{
+                return text.split(',').map(tag => tag.trim()).filter(tag => tag !== '');
+            }}
+
+            // datetime-local inputs hold local time without a zone.
+            function toIsoOrNull(localValue) {{
+                return localValue ? new Date(localValue).toISOString() : null;
+            }}
+
+            function toLocalInputValue(iso) {{
+                if (!iso) {{
+                    return '';
+                }}
+                const date = new Date(iso);
+                const offset = date.getTimezoneOffset() * 60000;
+                return new Date(date.getTime() - offset).toISOString().slice(0, 16);
+            }}
+
+            // The todo being added and its Idempotency-Key, kept until the
+            // backend answers so that sending it again cannot add it twice.
+            let pendingAdd = null;
+
+            function newIdempotencyKey() {{
+                const bytes = crypto.getRandomValues(new Uint8Array(16));
+                return Array.from(bytes, byte => byte.toString(16).padStart(2, '0')).join('');
+            }}
+
             async function addTodo() {{
                 const input = document.getElementById('todoInput');
                 const todoText = input.value.trim();
@@ -161,26 +318,42 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
                     return;
                 }}
 
-                if (todoText.length > 140) {{
+                if (charCount(todoText) > 140) {{
                     alert('Todo must be 140 characters or less');
                     return;
                 }}
 
+                const body = JSON.stringify({{
+                    content: todoText,
+                    priority: document.getElementById('prioritySelect').value,
+                    due_at: toIsoOrNull(document.getElementById('dueInput').value),
+                    tags: parseTags(document.getElementById('tagsInput').value)
+                }});
+                if (pendingAdd === null || pendingAdd.url !== todosUrl || pendingAdd.body !== body) {{
+                    pendingAdd = {{ url: todosUrl, body, key: newIdempotencyKey() }};
+                }}
+
                 try {{
-                    const response = await fetch('/todos', {{
+                    const response = await fetch(todosUrl, {{
                         method: 'POST',
                         headers: {{
                             'Content-Type': 'application/json',
+                            'Idempotency-Key': pendingAdd.key,
                         }},
-                        body: JSON.stringify({{ content: todoText }})
+                        body
                     }});
 
                     if (!response.ok) {{
+                        // Server errors leave nothing behind, so a retry may reuse the key.
+                        if (response.status < 500) {{
+                            pendingAdd = null;
+                        }}
                         const error = await response.json();
-                        alert('Error: ' + (error.error || 'Failed to create todo'));
+                        alert('Error: ' + (error.detail || 'Failed to create todo'));
                         return;
                     }}
 
+                    pendingAdd = null;
                     input.value = '';
                     updateCharCounter();
 
@@ -198,7 +371,7 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
 
                     if (!response.ok) {{
                         const error = await response.json();
-                        alert('Error: ' + (error.error || 'Failed to mark todo as done'));
+                        alert('Error: ' + (error.detail || 'Failed to mark todo as done'));
                         return;
                     }}
 
@@ -207,10 +380,52 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
                     alert('Error marking todo as done: ' + error.message);
                 }}
             }}
+
+            function toggleEdit(id) {{
+                const form = document.getElementById(`edit-${{id}}`);
+                if (form.hidden) {{
+                    form.elements.due_at.value = toLocalInputValue(form.dataset.due);
+                }}
+                form.hidden = !form.hidden;
+            }}
+
+            async function saveTodo(event, id) {{
+                event.preventDefault();
+                const form = event.target;
+
+                try {{
+                    const response = await fetch(`/todos/${{id}}`, {{
+                        method: 'PATCH',
+                        headers: {{
+                            'Content-Type': 'application/json',
+                        }},
+                        body: JSON.stringify({{
+                            priority: form.elements.priority.value,
+                            due_at: toIsoOrNull(form.elements.due_at.value),
+                            tags: parseTags(form.elements.tags.value)
+                        }})
+                    }});
+
+                    if (!response.ok) {{
+                        const error = await response.json();
+                        alert('Error: ' + (error.detail || 'Failed to update todo'));
+                        return;
+                    }}
+
+                    window.location.reload();
+                }} catch (error) {{
+                    alert('Error updating todo: ' + error.message);
+                }}
+            }}
         </script>
     </body>
     </html>"#,
         todo_items = todo_items_html,
+        list_switcher = list_switcher_html,
+        current_list_id = match current_list {
+            Some(list) if !list.is_default => list.id.to_string(),
+            _ => "null".to_string(),
+        },
         empty_state = if todos.is_empty() {
             r#"<div class="empty-state">No todos yet. Add one above!</div>"#
         } else {
@@ -223,12 +438,202 @@ async fn index(state: web::Data<AppState>) -> HttpResponse {
         .body(html)
 }
 
-async fn fetch_todos(client: &reqwest::Client, config: &Config) -> Result<Vec<Todo>, Box<dyn std::error::Error>> {
-    let response = client.get(&config.todo_backend_url).send().await?;
-    let todos: Vec<Todo> = response.json().await?;
+async fn fetch_lists(client: &reqwest::Client, config: &Config) -> Result<Vec<TodoList>, Box<dyn std::error::Error>> {
+    let lists = client
+        .get(&config.todo_lists_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(lists)
+}
+
+async fn fetch_todos(
+    client: &reqwest::Client,
+    cache: &Arc<Mutex<HashMap<String, CachedTodos>>>,
+    url: &str,
+) -> Result<Vec<Todo>, Box<dyn std::error::Error>> {
+    let cached_etag = cache
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .get(url)
+        .map(|cached| cached.etag.clone());
+
+    let mut request = client.get(url);
+    if let Some(etag) = &cached_etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+
+    let mut response = request.send().await?;
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        let cached_todos = cache
+            .lock()
+            .map_err(|e| format!("Lock error: {}", e))?
+            .get(url)
+            .map(|cached| cached.todos.clone());
+
+        if let Some(todos) = cached_todos {
+            tracing::debug!("Todo list unchanged, reusing cached copy");
+            return Ok(todos);
+        }
+
+        // The cached copy was dropped while the request was in flight, so the
+        // 304 has nothing to refer to; ask for the full list instead.
+        tracing::debug!("Cached todo list is gone, fetching it again");
+        response = client.get(url).send().await?;
+    }
+
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let todos: Vec<Todo> = response.error_for_status()?.json().await?;
+
+    let mut cache = cache.lock().map_err(|e| format!("Lock error: {}", e))?;
+    match etag {
+        Some(etag) => {
+            cache.insert(url.to_string(), CachedTodos { etag, todos: todos.clone() });
+        }
+        None => {
+            cache.remove(url);
+        }
+    }
+
     Ok(todos)
 }
 
+/// A select of every list, showing how many open todos each has. Empty when
+/// the lists could not be fetched.
+fn list_switcher_html(lists: &[TodoList], current: Option<&TodoList>) -> String {
+    if lists.is_empty() {
+        return String::new();
+    }
+
+    let options: String = lists
+        .iter()
+        .map(|list| {
+            let selected = if current.is_some_and(|current| current.id == list.id) {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{}>{} ({})</option>"#,
+                list.id,
+                selected,
+                html_escape(&list.name),
+                list.open_count
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<div class="list-switcher"><label>List <select id="listSelect" onchange="switchList(this.value)">{}</select></label><button class="list-button" onclick="createList()">New list</button></div>"#,
+        options
+    )
+}
+
+fn todo_item_html(todo: &Todo, now: DateTime<Utc>) -> String {
+    let mut classes = format!("todo-item priority-{}", html_escape(&todo.priority));
+    if todo.done {
+        classes.push_str(" done");
+    }
+    if todo.is_overdue(now) {
+        classes.push_str(" overdue");
+    }
+
+    let done_button = if !todo.done {
+        format!(r#"<button class="done-button" onclick="markDone({})">Done</button>"#, todo.id)
+    } else {
+        String::from(r#"<span class="done-badge">✓ Done</span>"#)
+    };
+
+    let priority_badge = if todo.priority == "normal" {
+        String::new()
+    } else {
+        format!(
+            r#"<span class="priority-badge {0}">{0}</span>"#,
+            html_escape(&todo.priority)
+        )
+    };
+
+    format!(
+        r#"<li class="{}"><div><span class="todo-content">{}</span>{}{}{}</div><div class="todo-actions">{}<button class="edit-button" onclick="toggleEdit({})">Edit</button></div></li>"#,
+        classes,
+        html_escape(&todo.content),
+        priority_badge,
+        todo_meta_html(todo, now),
+        todo_edit_form_html(todo),
+        done_button,
+        todo.id
+    )
+}
+
+/// Inline form for the priority, due date and tags, hidden until "Edit" is
+/// pressed. The due date is filled in by the browser, in local time.
+fn todo_edit_form_html(todo: &Todo) -> String {
+    let options: String = ["low", "normal", "high"]
+        .iter()
+        .map(|priority| {
+            let selected = if *priority == todo.priority { " selected" } else { "" };
+            format!(r#"<option value="{0}"{1}>{0}</option>"#, priority, selected)
+        })
+        .collect();
+
+    format!(
+        r#"<form class="todo-edit" id="edit-{}" data-due="{}" hidden onsubmit="saveTodo(event, {})"><select name="priority">{}</select><input type="datetime-local" name="due_at"><input type="text" name="tags" value="{}" placeholder="Tags, comma-separated"><button type="submit">Save</button></form>"#,
+        todo.id,
+        todo.due_at.map(|due_at| due_at.to_rfc3339()).unwrap_or_default(),
+        todo.id,
+        options,
+        html_escape(&todo.tags.join(", "))
+    )
+}
+
+fn todo_meta_html(todo: &Todo, now: DateTime<Utc>) -> String {
+    let mut parts = Vec::new();
+
+    if let Some(due_at) = todo.due_at {
+        if todo.is_overdue(now) {
+            parts.push(format!(
+                r#"<span class="overdue-label">Overdue since {}</span>"#,
+                format_timestamp(due_at)
+            ));
+        } else {
+            parts.push(format!("Due {}", format_timestamp(due_at)));
+        }
+    }
+    if let Some(created_at) = todo.created_at {
+        parts.push(format!("Added {}", format_timestamp(created_at)));
+    }
+    if let Some(completed_at) = todo.completed_at {
+        parts.push(format!("Finished {}", format_timestamp(completed_at)));
+    }
+
+    if !todo.tags.is_empty() {
+        let tags: String = todo
+            .tags
+            .iter()
+            .map(|tag| format!(r#"<span class="tag">{}</span>"#, html_escape(tag)))
+            .collect();
+        parts.push(tags);
+    }
+
+    if parts.is_empty() {
+        return String::new();
+    }
+
+    format!(r#"<span class="todo-meta">{}</span>"#, parts.join(" · "))
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
 fn html_escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
@@ -306,6 +711,7 @@ pub fn run(listener: TcpListener) -> Result<Server, std::io::Error> {
     let state = web::Data::new(AppState {
         client: reqwest::Client::new(),
         image_lock: Arc::new(Mutex::new(())),
+        todo_cache: Arc::new(Mutex::new(HashMap::new())),
         config,
     });
 
@@ -0,0 +1,27 @@
+ALTER TABLE todos ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+ALTER TABLE todos ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ;
+ALTER TABLE todos ADD COLUMN IF NOT EXISTS completed_at TIMESTAMPTZ;
+
+UPDATE todos SET updated_at = created_at WHERE updated_at IS NULL;
+
+ALTER TABLE todos ALTER COLUMN updated_at SET DEFAULT NOW();
+ALTER TABLE todos ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION todos_touch_timestamps() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at := NOW();
+
+    IF NEW.done AND NOT OLD.done THEN
+        NEW.completed_at := NOW();
+    ELSIF NOT NEW.done THEN
+        NEW.completed_at := NULL;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todos_touch_timestamps ON todos;
+CREATE TRIGGER todos_touch_timestamps
+    BEFORE UPDATE ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_touch_timestamps();
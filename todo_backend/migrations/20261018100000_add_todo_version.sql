@@ -0,0 +1,16 @@
+ALTER TABLE todos ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+
+CREATE OR REPLACE FUNCTION todos_touch_timestamps() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at := NOW();
+    NEW.version := OLD.version + 1;
+
+    IF NEW.done AND NOT OLD.done THEN
+        NEW.completed_at := NOW();
+    ELSIF NOT NEW.done THEN
+        NEW.completed_at := NULL;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
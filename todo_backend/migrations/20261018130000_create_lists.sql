@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS lists (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE CHECK (length(name) BETWEEN 1 AND 64),
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- At most one list is the default, which the `/todos` routes use.
+CREATE UNIQUE INDEX IF NOT EXISTS lists_single_default_idx ON lists (is_default) WHERE is_default;
+
+INSERT INTO lists (name, is_default)
+SELECT 'Inbox', TRUE
+WHERE NOT EXISTS (SELECT 1 FROM lists WHERE is_default);
+
+CREATE OR REPLACE FUNCTION lists_touch_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at := NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS lists_touch_updated_at ON lists;
+CREATE TRIGGER lists_touch_updated_at
+    BEFORE UPDATE ON lists
+    FOR EACH ROW EXECUTE FUNCTION lists_touch_updated_at();
+
+-- Todos are deleted with their list by the application, which records an
+-- event for each; RESTRICT catches anything that bypasses it.
+ALTER TABLE todos ADD COLUMN IF NOT EXISTS list_id INTEGER REFERENCES lists (id) ON DELETE RESTRICT;
+-- Backfilling is not a change to the todos, so their versions stay put.
+ALTER TABLE todos DISABLE TRIGGER todos_touch_timestamps;
+UPDATE todos SET list_id = (SELECT id FROM lists WHERE is_default) WHERE list_id IS NULL;
+ALTER TABLE todos ENABLE TRIGGER todos_touch_timestamps;
+ALTER TABLE todos ALTER COLUMN list_id SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS todos_list_id_idx ON todos (list_id);
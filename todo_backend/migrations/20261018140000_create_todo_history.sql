@@ -0,0 +1,52 @@
+-- Append-only record of every change to a todo. `old_value` and `new_value`
+-- hold the todo as the API returns it; `todo_id` has no foreign key so the
+-- history outlives the todo.
+CREATE TABLE IF NOT EXISTS todo_history (
+    id BIGSERIAL PRIMARY KEY,
+    todo_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    actor TEXT,
+    old_value JSONB,
+    new_value JSONB,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS todo_history_todo_id_idx ON todo_history (todo_id, id);
+CREATE INDEX IF NOT EXISTS todo_history_changed_at_idx ON todo_history (changed_at);
+
+CREATE OR REPLACE FUNCTION todo_history_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'todo_history is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todo_history_append_only ON todo_history;
+CREATE TRIGGER todo_history_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON todo_history
+    FOR EACH STATEMENT EXECUTE FUNCTION todo_history_append_only();
+
+-- Todos that predate the history start with a `baseline` entry holding their
+-- current state as of their last update; earlier changes are not known.
+INSERT INTO todo_history (todo_id, action, new_value, changed_at)
+SELECT
+    todos.id,
+    'baseline',
+    jsonb_build_object(
+        'id', todos.id,
+        'content', todos.content,
+        'done', todos.done,
+        'created_at', todos.created_at,
+        'updated_at', todos.updated_at,
+        'completed_at', todos.completed_at,
+        'version', todos.version,
+        'priority', todos.priority,
+        'due_at', todos.due_at,
+        'tags', ARRAY(
+            SELECT tags.name FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id
+            WHERE todo_tags.todo_id = todos.id ORDER BY tags.name COLLATE "C"
+        ),
+        'list_id', todos.list_id
+    ),
+    todos.updated_at
+FROM todos
+WHERE NOT EXISTS (SELECT 1 FROM todo_history WHERE todo_history.todo_id = todos.id);
@@ -0,0 +1,4 @@
+-- The relay holds back a todo's events while an older one awaits a retry.
+CREATE INDEX IF NOT EXISTS todo_events_outbox_pending_todo_idx
+    ON todo_events_outbox ((payload->'todo'->'id'), id)
+    WHERE delivered_at IS NULL;
@@ -0,0 +1,6 @@
+-- Keys are chosen by clients, so two clients may pick the same one: scope
+-- them to the actor sending them. Requests without an actor share ''.
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS actor TEXT NOT NULL DEFAULT '';
+
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (actor, key);
@@ -0,0 +1,329 @@
+//! `POST /todos:batch`: creates, updates and deletes todos in a single
+//! transaction, so scripts need not send a request per todo.
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+
+use crate::error::Problem;
+use crate::{
+    apply_todo_patch, check_new_todo, check_patch, etag, insert_todo, not_found_or_precondition_failed, remove_todo,
+    Actor, AppState, ContentPolicy, CreateTodo, Error, IfMatch, PatchTodo, Result, Todo,
+};
+
+pub const MAX_OPERATIONS: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Either every operation is applied or none is; the first failure rolls
+    /// the batch back.
+    #[default]
+    AllOrNothing,
+    /// Operations succeed or fail on their own; failed ones are rolled back
+    /// and the rest are committed.
+    PerItem,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<Operation>,
+}
+
+/// One operation of a batch. `if_match` takes the same tags as the `If-Match`
+/// header of the equivalent single-todo request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// Adds a todo to `list_id`, or to the default list.
+    Create {
+        #[serde(default)]
+        list_id: Option<i32>,
+        todo: CreateTodo,
+    },
+    Update {
+        id: i32,
+        #[serde(default)]
+        if_match: Option<String>,
+        patch: PatchTodo,
+    },
+    /// Moves a todo to the trash.
+    Delete {
+        id: i32,
+        #[serde(default)]
+        if_match: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub mode: BatchMode,
+    /// Whether the transaction was committed; never for an all-or-nothing
+    /// batch with a failed operation.
+    pub committed: bool,
+    /// One result per operation, in request order.
+    pub results: Vec<OperationResult>,
+}
+
+/// The outcome of an operation, with the status the equivalent single-todo
+/// request would have returned. Operations of a failed all-or-nothing batch
+/// other than the failing one report `424 Failed Dependency`.
+#[derive(Debug, Serialize)]
+pub struct OperationResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Problem>,
+}
+
+impl OperationResult {
+    fn applied(status: StatusCode, todo: Option<Todo>) -> Self {
+        Self {
+            status: status.as_u16(),
+            etag: todo.as_ref().map(etag::todo_etag),
+            todo,
+            error: None,
+        }
+    }
+
+    fn failed(error: &Error) -> Self {
+        Self {
+            status: error.status_code().as_u16(),
+            etag: match error {
+                Error::PreconditionFailed { etag } => Some(etag.clone()),
+                _ => None,
+            },
+            todo: None,
+            error: Some(error.problem(None, None)),
+        }
+    }
+
+    fn not_applied() -> Self {
+        Self {
+            status: StatusCode::FAILED_DEPENDENCY.as_u16(),
+            etag: None,
+            todo: None,
+            error: None,
+        }
+    }
+}
+
+pub(crate) async fn batch_todos(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    batch: web::Json<BatchRequest>,
+) -> Result<HttpResponse> {
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+    let BatchRequest { mode, operations } = batch.into_inner();
+
+    if operations.is_empty() {
+        return Err(Error::Validation("A batch needs at least one operation".to_string()));
+    }
+    if operations.len() > MAX_OPERATIONS {
+        return Err(Error::Validation(format!(
+            "A batch can hold at most {} operations",
+            MAX_OPERATIONS
+        )));
+    }
+
+    tracing::info!("Applying a batch of {} operations ({:?})", operations.len(), mode);
+
+    let count = operations.len();
+    let mut results = Vec::with_capacity(count);
+    let mut tx = state.db_pool.begin().await?;
+
+    for (index, operation) in operations.into_iter().enumerate() {
+        let outcome = match mode {
+            BatchMode::AllOrNothing => apply(&mut tx, &state.content_policy, &actor, operation).await,
+            BatchMode::PerItem => {
+                let mut savepoint = tx.begin().await?;
+                let outcome = apply(&mut savepoint, &state.content_policy, &actor, operation).await;
+                match outcome {
+                    Ok(_) => savepoint.commit().await?,
+                    Err(_) => savepoint.rollback().await?,
+                }
+                outcome
+            }
+        };
+
+        match outcome {
+            Ok(result) => results.push(result),
+            Err(e) if e.is_internal() => return Err(e),
+            Err(e) => {
+                tracing::warn!("Batch operation {} failed: {}", index, e);
+
+                if mode == BatchMode::AllOrNothing {
+                    tx.rollback().await?;
+
+                    let mut results: Vec<_> = (0..count).map(|_| OperationResult::not_applied()).collect();
+                    results[index] = OperationResult::failed(&e);
+
+                    return Ok(HttpResponse::build(e.status_code())
+                        .content_type("application/json; charset=utf-8")
+                        .json(BatchResponse {
+                            mode,
+                            committed: false,
+                            results,
+                        }));
+                }
+
+                results.push(OperationResult::failed(&e));
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    let applied = results.iter().filter(|result| result.error.is_none()).count();
+    tracing::info!("Applied {} of {} batch operations", applied, count);
+
+    if applied > 0 {
+        state.outbox_wakeup.notify_one();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(BatchResponse {
+            mode,
+            committed: true,
+            results,
+        }))
+}
+
+async fn apply(
+    conn: &mut PgConnection,
+    policy: &ContentPolicy,
+    actor: &Actor,
+    operation: Operation,
+) -> Result<OperationResult> {
+    match operation {
+        Operation::Create { list_id, todo } => {
+            let new_todo = check_new_todo(policy, todo)?;
+            let todo = insert_todo(conn, actor, list_id, &new_todo)
+                .await?
+                .ok_or(Error::ListNotFound)?;
+
+            Ok(OperationResult::applied(StatusCode::CREATED, Some(todo)))
+        }
+        Operation::Update { id, if_match, patch } => {
+            let if_match = IfMatch::parse(if_match.as_deref(), id);
+            let patch = check_patch(policy, patch)?;
+
+            match apply_todo_patch(conn, actor, id, &patch, if_match.versions()).await? {
+                Some(todo) => Ok(OperationResult::applied(StatusCode::OK, Some(todo))),
+                None => Err(not_found_or_precondition_failed(conn, id, &if_match).await),
+            }
+        }
+        Operation::Delete { id, if_match } => {
+            let if_match = IfMatch::parse(if_match.as_deref(), id);
+
+            match remove_todo(conn, actor, id, if_match.versions()).await? {
+                Some(_) => Ok(OperationResult::applied(StatusCode::NO_CONTENT, None)),
+                None => Err(not_found_or_precondition_failed(conn, id, &if_match).await),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+    use sqlx::postgres::PgPool;
+
+    use super::*;
+
+    /// Sends a batch whose second operation fails, returning the response
+    /// status, the response and the todos in the database afterwards.
+    async fn batch_with_failure(pool: PgPool, mode: &str) -> (StatusCode, serde_json::Value, Vec<String>) {
+        let app = test::init_service(
+            App::new()
+                .app_data(AppState::for_tests(pool.clone()).await)
+                .route("/todos:batch", web::post().to(batch_todos)),
+        )
+        .await;
+
+        let req = TestRequest::post().uri("/todos:batch").set_json(serde_json::json!({
+            "mode": mode,
+            "operations": [
+                { "op": "create", "todo": { "content": "First" } },
+                { "op": "delete", "id": 999_999 },
+                { "op": "create", "todo": { "content": "Third" } },
+            ]
+        }));
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = test::read_body_json(res).await;
+
+        let contents = sqlx::query_scalar("SELECT content FROM todos ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        (status, body, contents)
+    }
+
+    fn statuses(body: &serde_json::Value) -> Vec<u64> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn all_or_nothing_batches_roll_back_on_failure(pool: PgPool) {
+        let (status, body, contents) = batch_with_failure(pool, "all_or_nothing").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["committed"], false);
+        // The first operation was rolled back and the third never ran.
+        assert_eq!(statuses(&body), [424, 404, 424]);
+        assert_eq!(body["results"][1]["error"]["code"], "todo_not_found");
+        assert!(contents.is_empty());
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn per_item_batches_keep_the_other_operations(pool: PgPool) {
+        let (status, body, contents) = batch_with_failure(pool, "per_item").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["committed"], true);
+        assert_eq!(statuses(&body), [201, 404, 201]);
+        assert_eq!(body["results"][0]["todo"]["content"], "First");
+        assert_eq!(contents, ["First", "Third"]);
+    }
+
+    #[test]
+    fn operations_are_tagged_by_op() {
+        let batch: BatchRequest = serde_json::from_value(serde_json::json!({
+            "operations": [
+                { "op": "create", "todo": { "content": "Buy milk", "tags": ["errands"] } },
+                { "op": "update", "id": 3, "if_match": "\"3.2\"", "patch": { "due_at": null } },
+                { "op": "delete", "id": 4 },
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(batch.mode, BatchMode::AllOrNothing);
+        assert!(matches!(&batch.operations[0], Operation::Create { list_id: None, todo } if todo.tags == ["errands"]));
+        assert!(matches!(
+            &batch.operations[1],
+            Operation::Update { id: 3, if_match: Some(_), patch } if patch.due_at == Some(None)
+        ));
+        assert!(matches!(&batch.operations[2], Operation::Delete { id: 4, if_match: None }));
+
+        let mode: BatchMode = serde_json::from_value(serde_json::json!("per_item")).unwrap();
+        assert_eq!(mode, BatchMode::PerItem);
+    }
+}
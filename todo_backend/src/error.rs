@@ -0,0 +1,305 @@
+//! Errors returned by the handlers, rendered as RFC 7807
+//! `application/problem+json` bodies with a stable `code` and the id of the
+//! request, as logged by `TracingLogger`.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, PayloadError};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse, ResponseError};
+use serde::Serialize;
+use thiserror::Error;
+use tracing_actix_web::RequestId;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+/// Postgres `unique_violation`.
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Todo not found")]
+    TodoNotFound,
+
+    #[error("List not found")]
+    ListNotFound,
+
+    #[error("{0}")]
+    Validation(String),
+
+    #[error("{0}")]
+    PayloadTooLarge(String),
+
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+
+    /// No route matches the request path.
+    #[error("No resource at this path")]
+    RouteNotFound,
+
+    #[error("Method not allowed for this resource")]
+    MethodNotAllowed,
+
+    /// `If-Match` did not name the current version, whose tag is `etag`.
+    #[error("Todo has been modified by another request")]
+    PreconditionFailed { etag: String },
+
+    #[error("{0}")]
+    Conflict(String),
+
+    /// An `Idempotency-Key` was sent again with a different request.
+    #[error("Idempotency-Key has already been used for a different request")]
+    IdempotencyKeyReused,
+
+    #[error("Database error: {0}")]
+    Database(sqlx::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match e.as_database_error() {
+            Some(db) if db.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                Error::Conflict("A conflicting record already exists".to_string())
+            }
+            _ => Error::Database(e),
+        }
+    }
+}
+
+/// RFC 7807 problem details. `type` is left as `about:blank`, so `title` is
+/// the status' reason phrase and `code` tells errors with the same status
+/// apart.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Error {
+    /// Stable, machine-readable identifier of the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::TodoNotFound => "todo_not_found",
+            Error::ListNotFound => "list_not_found",
+            Error::Validation(_) => "validation_failed",
+            Error::PayloadTooLarge(_) => "payload_too_large",
+            Error::UnsupportedMediaType(_) => "unsupported_media_type",
+            Error::RouteNotFound => "not_found",
+            Error::MethodNotAllowed => "method_not_allowed",
+            Error::PreconditionFailed { .. } => "precondition_failed",
+            Error::Conflict(_) => "conflict",
+            Error::IdempotencyKeyReused => "idempotency_key_reused",
+            Error::Database(_) | Error::Serialization(_) => "internal_error",
+        }
+    }
+
+    pub(crate) fn is_internal(&self) -> bool {
+        matches!(self, Error::Database(_) | Error::Serialization(_))
+    }
+
+    pub fn problem(&self, instance: Option<String>, request_id: Option<String>) -> Problem {
+        let status = self.status_code();
+
+        Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            // Internal details stay in the logs.
+            detail: if self.is_internal() {
+                "Internal server error".to_string()
+            } else {
+                self.to_string()
+            },
+            code: self.code(),
+            instance,
+            request_id,
+        }
+    }
+
+    fn problem_response(&self, problem: &Problem) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type(PROBLEM_CONTENT_TYPE);
+
+        if let Error::PreconditionFailed { etag } = self {
+            response.insert_header((header::ETAG, etag.clone()));
+        }
+
+        response.json(problem)
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::TodoNotFound | Error::ListNotFound | Error::RouteNotFound => StatusCode::NOT_FOUND,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Error::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            Error::Conflict(_) | Error::IdempotencyKeyReused => StatusCode::CONFLICT,
+            Error::Database(_) | Error::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.problem_response(&self.problem(None, None))
+    }
+}
+
+impl From<JsonPayloadError> for Error {
+    fn from(e: JsonPayloadError) -> Self {
+        match e {
+            JsonPayloadError::OverflowKnownLength { .. }
+            | JsonPayloadError::Overflow { .. }
+            | JsonPayloadError::Payload(PayloadError::Overflow) => Error::PayloadTooLarge(e.to_string()),
+            JsonPayloadError::ContentType => Error::UnsupportedMediaType(e.to_string()),
+            _ => Error::Validation(e.to_string()),
+        }
+    }
+}
+
+/// Answers requests that match no route.
+pub async fn route_not_found() -> Result<HttpResponse> {
+    Err(Error::RouteNotFound)
+}
+
+/// Middleware echoing the request id in `X-Request-Id` and filling in the
+/// `instance` and `request_id` of problem responses, which
+/// [`ResponseError::error_response`] cannot see. Must be wrapped inside
+/// `TracingLogger`.
+pub async fn problem_details(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> std::result::Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().map(ToString::to_string);
+    let instance = req.path().to_string();
+
+    let mut res = next.call(req).await?.map_into_boxed_body();
+
+    // A path that matches with another method gets an empty 405 from actix.
+    let method_not_allowed = (res.status() == StatusCode::METHOD_NOT_ALLOWED && res.response().error().is_none())
+        .then_some(&Error::MethodNotAllowed);
+
+    let problem = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<Error>())
+        .or(method_not_allowed)
+        .map(|error| {
+            if error.is_internal() {
+                tracing::error!("Request failed: {}", error);
+            }
+            error.problem_response(&error.problem(Some(instance), request_id.clone()))
+        });
+
+    if let Some(response) = problem {
+        res = res.into_response(response);
+    }
+
+    if let Some(id) = request_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, id);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problems_carry_a_stable_code() {
+        let problem = Error::TodoNotFound.problem(Some("/todos/7".to_string()), Some("abc".to_string()));
+        let value = serde_json::to_value(&problem).unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Todo not found",
+                "code": "todo_not_found",
+                "instance": "/todos/7",
+                "request_id": "abc",
+            })
+        );
+    }
+
+    #[test]
+    fn internal_errors_hide_their_details() {
+        let error = Error::Database(sqlx::Error::PoolTimedOut);
+        let problem = error.problem(None, None);
+
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(problem.code, "internal_error");
+        assert_eq!(problem.detail, "Internal server error");
+    }
+
+    #[test]
+    fn json_errors_keep_their_status() {
+        let too_large = Error::from(JsonPayloadError::OverflowKnownLength { length: 10, limit: 5 });
+        let wrong_type = Error::from(JsonPayloadError::ContentType);
+        let malformed = Error::from(JsonPayloadError::Deserialize(
+            serde_json::from_str::<u8>("x").unwrap_err(),
+        ));
+
+        assert_eq!(too_large.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(wrong_type.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(malformed.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn unmatched_routes_are_problems() {
+        use actix_web::{middleware::from_fn, test, web, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(problem_details))
+                .service(web::resource("/todos").route(web::get().to(HttpResponse::Ok)))
+                .default_service(web::to(route_not_found)),
+        )
+        .await;
+
+        for (req, status, code) in [
+            (test::TestRequest::get().uri("/nope"), StatusCode::NOT_FOUND, "not_found"),
+            (test::TestRequest::delete().uri("/todos"), StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed"),
+        ] {
+            let res = test::call_service(&app, req.to_request()).await;
+
+            assert_eq!(res.status(), status);
+            assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_CONTENT_TYPE);
+            let problem: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(problem["code"], code);
+        }
+    }
+
+    #[test]
+    fn precondition_failures_return_the_current_etag() {
+        let response = Error::PreconditionFailed {
+            etag: "\"7.3\"".to_string(),
+        }
+        .error_response();
+
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"7.3\"");
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_CONTENT_TYPE);
+    }
+}
@@ -0,0 +1,130 @@
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+
+use crate::Todo;
+
+/// Strong entity tag for a single todo. It changes whenever the row's
+/// `version` is bumped by the `todos_touch_timestamps` trigger.
+pub fn todo_etag(todo: &Todo) -> String {
+    format!("\"{}.{}\"", todo.id, todo.version)
+}
+
+/// Entity tag for a serialized list response, derived from the body itself
+/// so that any change in membership, order or content produces a new tag.
+/// SHA-256 keeps the tag the same across releases and replicas, which lets
+/// clients revalidate against any of them.
+pub fn list_etag(body: &[u8]) -> String {
+    format!("\"list-{:x}\"", Sha256::digest(body))
+}
+
+/// The `If-Match` precondition of a mutation on the todo with `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    /// No header, or `*`: any existing version may be modified.
+    Any,
+    /// Only these versions may be modified. Tags for other todos, weak tags
+    /// and malformed tags never match, so the list may be empty.
+    Versions(Vec<i32>),
+}
+
+impl IfMatch {
+    pub fn from_request(req: &HttpRequest, id: i32) -> Self {
+        Self::parse(
+            req.headers()
+                .get_all(header::IF_MATCH)
+                .filter_map(|value| value.to_str().ok()),
+            id,
+        )
+    }
+
+    /// Parses `If-Match` header values, each of which may list several tags.
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>, id: i32) -> Self {
+        let values: Vec<&str> = values
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect();
+
+        if values.is_empty() || values.contains(&"*") {
+            return Self::Any;
+        }
+
+        let versions = values
+            .into_iter()
+            .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"'))
+            .filter_map(|tag| tag.split_once('.'))
+            .filter(|(tag_id, _)| tag_id.parse() == Ok(id))
+            .filter_map(|(_, version)| version.parse().ok())
+            .collect();
+
+        Self::Versions(versions)
+    }
+
+    /// Versions to pass as the `version = ANY(...)` filter, `None` meaning
+    /// no restriction.
+    pub fn versions(&self) -> Option<&[i32]> {
+        match self {
+            Self::Any => None,
+            Self::Versions(versions) => Some(versions),
+        }
+    }
+}
+
+/// Whether an `If-None-Match` header on the request matches `etag`.
+pub fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn if_match_keeps_versions_of_the_requested_todo() {
+        let req = TestRequest::default()
+            .insert_header((header::IF_MATCH, r#""7.3", "8.1", W/"7.4", "7.5""#))
+            .to_http_request();
+
+        assert_eq!(IfMatch::from_request(&req, 7), IfMatch::Versions(vec![3, 5]));
+    }
+
+    #[test]
+    fn if_match_wildcard_or_missing_matches_anything() {
+        let req = TestRequest::default()
+            .insert_header((header::IF_MATCH, "*"))
+            .to_http_request();
+
+        assert_eq!(IfMatch::from_request(&req, 1), IfMatch::Any);
+        assert_eq!(IfMatch::from_request(&TestRequest::default().to_http_request(), 1), IfMatch::Any);
+    }
+
+    #[test]
+    fn list_etags_are_stable() {
+        assert_eq!(
+            list_etag(b"[]"),
+            "\"list-4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945\""
+        );
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        let etag = list_etag(b"[]");
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, format!("\"other\", W/{}", etag)))
+            .to_http_request();
+
+        assert!(if_none_match(&req, &etag));
+        assert!(!if_none_match(&TestRequest::default().to_http_request(), &etag));
+    }
+}
@@ -0,0 +1,156 @@
+//! Append-only change history of todos, recorded in the same transaction as
+//! each change, and the point-in-time view built from it.
+
+use actix_web::http::header::HeaderName;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::{PgConnection, PgPool};
+use sqlx::types::Json;
+use sqlx::FromRow;
+use todo_events::TodoAction;
+
+use crate::{Error, Result, Todo};
+
+pub const DEFAULT_ACTOR_HEADER: &str = "x-actor";
+
+const MAX_ACTOR_CHARS: usize = 128;
+
+/// Who made a change, as named by the actor header. `None` when the header
+/// is absent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Actor(Option<String>);
+
+impl Actor {
+    pub fn from_request(req: &HttpRequest, header: &HeaderName) -> Result<Self> {
+        let Some(value) = req.headers().get(header) else {
+            return Ok(Self(None));
+        };
+
+        let invalid = || {
+            Error::Validation(format!(
+                "{} must be printable text of at most {} characters",
+                header, MAX_ACTOR_CHARS
+            ))
+        };
+
+        // Names are often not ASCII, which `HeaderValue::to_str` would reject.
+        let actor = std::str::from_utf8(value.as_bytes()).map_err(|_| invalid())?.trim();
+        if actor.chars().count() > MAX_ACTOR_CHARS || actor.chars().any(char::is_control) {
+            return Err(invalid());
+        }
+
+        Ok(Self(Some(actor.to_string()).filter(|actor| !actor.is_empty())))
+    }
+
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+/// Reads the name of the header carrying the actor from `TODO_ACTOR_HEADER`,
+/// e.g. `X-Forwarded-User` behind an authenticating proxy.
+pub fn actor_header_from_env() -> Result<HeaderName, String> {
+    let name = std::env::var("TODO_ACTOR_HEADER").unwrap_or_else(|_| DEFAULT_ACTOR_HEADER.to_string());
+    HeaderName::try_from(name.as_str()).map_err(|_| format!("TODO_ACTOR_HEADER '{}' is not a valid header name", name))
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub todo_id: i32,
+    /// A [`TodoAction`], or `baseline` for the state of a todo that predates
+    /// the history.
+    pub action: String,
+    pub actor: Option<String>,
+    pub old_value: Option<Json<Todo>>,
+    pub new_value: Option<Json<Todo>>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Appends a change to the history. `todo` is the todo after the change, or
+/// its last state for `purged`. Must be called on the transaction making the
+/// change.
+pub async fn record(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    action: TodoAction,
+    todo: &Todo,
+    previous: Option<&Todo>,
+) -> Result<(), sqlx::Error> {
+    // A todo in the trash no longer counts as existing.
+    let (old_value, new_value) = match action {
+        TodoAction::Deleted | TodoAction::Purged => (previous.or(Some(todo)), None),
+        _ => (previous, Some(todo)),
+    };
+
+    sqlx::query(
+        "INSERT INTO todo_history (todo_id, action, actor, old_value, new_value) \
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(todo.id)
+    .bind(action.as_str())
+    .bind(actor.as_deref())
+    .bind(old_value.map(Json))
+    .bind(new_value.map(Json))
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Every change to the todo, oldest first.
+pub async fn fetch_history(pool: &PgPool, todo_id: i32) -> Result<Vec<HistoryEntry>, sqlx::Error> {
+    sqlx::query_as::<_, HistoryEntry>(
+        "SELECT id, todo_id, action, actor, old_value, new_value, changed_at \
+         FROM todo_history WHERE todo_id = $1 ORDER BY id",
+    )
+    .bind(todo_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// The todos of a list as they were at `as_of`, newest first, rebuilt from
+/// the latest change to each todo at or before that time.
+pub async fn todos_as_of(pool: &PgPool, list_id: i32, as_of: DateTime<Utc>) -> Result<Vec<Todo>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (Json<Todo>,)>(
+        "SELECT new_value FROM ( \
+             SELECT DISTINCT ON (todo_id) new_value FROM todo_history \
+             WHERE changed_at <= $1 \
+             ORDER BY todo_id, id DESC \
+         ) latest \
+         WHERE new_value IS NOT NULL AND (new_value->>'list_id')::INTEGER = $2 \
+         ORDER BY (new_value->>'created_at')::TIMESTAMPTZ DESC, (new_value->>'id')::INTEGER DESC",
+    )
+    .bind(as_of)
+    .bind(list_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(Json(todo),)| todo).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn actor_comes_from_the_configured_header() {
+        let header = HeaderName::from_static("x-forwarded-user");
+
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-User", " Zoë Ångström "))
+            .to_http_request();
+        assert_eq!(Actor::from_request(&req, &header).unwrap().as_deref(), Some("Zoë Ångström"));
+
+        let req = TestRequest::default().insert_header(("X-Actor", "mallory")).to_http_request();
+        assert_eq!(Actor::from_request(&req, &header).unwrap(), Actor(None));
+
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-User", "a".repeat(MAX_ACTOR_CHARS + 1)))
+            .to_http_request();
+        assert!(matches!(Actor::from_request(&req, &header), Err(Error::Validation(_))));
+    }
+}
@@ -0,0 +1,479 @@
+//! `Idempotency-Key` support. A mutation sent with a key is handled once;
+//! repeating it with the same key replays the stored response instead, so
+//! clients can retry a request that timed out without doing it twice.
+//! Keys are scoped to the [`Actor`] sending them.
+
+use std::time::Duration;
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::{HttpMessage, HttpResponse};
+use futures_util::StreamExt;
+use sqlx::postgres::PgPool;
+use sqlx::types::Json;
+use sqlx::FromRow;
+
+use crate::history::Actor;
+use crate::{transfer, AppState, Error, Result};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on replayed responses.
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+const MAX_KEY_CHARS: usize = 255;
+/// A first request still unanswered after this long is taken to have died
+/// with its server, and the key is free again.
+const ABANDONED_AFTER: Duration = Duration::from_secs(300);
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Reads how long keys are kept from `TODO_IDEMPOTENCY_TTL_HOURS`, falling
+/// back to a day.
+pub fn ttl_from_env() -> Result<Duration, String> {
+    match std::env::var("TODO_IDEMPOTENCY_TTL_HOURS") {
+        Ok(hours) => hours
+            .parse::<u64>()
+            .ok()
+            .filter(|hours| *hours > 0)
+            .and_then(|hours| hours.checked_mul(3600))
+            .map(Duration::from_secs)
+            .ok_or_else(|| "TODO_IDEMPOTENCY_TTL_HOURS must be a positive integer".to_string()),
+        Err(_) => Ok(DEFAULT_TTL),
+    }
+}
+
+/// An `Idempotency-Key` and the actor who sent it, `""` when anonymous.
+#[derive(Debug)]
+struct Key {
+    actor: String,
+    key: String,
+}
+
+#[derive(Debug, FromRow)]
+struct StoredRequest {
+    same_request: bool,
+    status: Option<i16>,
+    headers: Option<Json<Vec<(String, String)>>>,
+    body: Option<Vec<u8>>,
+}
+
+/// Middleware handling mutations that carry an `Idempotency-Key`. Must be
+/// wrapped inside `problem_details`, which renders the errors it returns.
+pub async fn idempotent_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> std::result::Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let is_mutation = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY).filter(|_| is_mutation) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let key = match parse_key(value) {
+        Ok(key) => key,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .expect("AppState should be registered");
+    let actor = match Actor::from_request(req.request(), &state.actor_header) {
+        Ok(actor) => actor,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+    let key = Key {
+        actor: actor.as_deref().unwrap_or_default().to_string(),
+        key,
+    };
+
+    let body = match read_body(req.take_payload()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+    let fingerprint = fingerprint(&req, &body);
+
+    let claimed = match claim(&state.db_pool, &key, &fingerprint, state.idempotency_ttl).await {
+        Ok(claimed) => claimed,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+
+    if let Some(stored) = claimed {
+        let response = match stored {
+            StoredRequest { same_request: false, .. } => Err(Error::IdempotencyKeyReused),
+            StoredRequest { status: None, .. } => Err(Error::Conflict(
+                "A request with this Idempotency-Key is still being processed".to_string(),
+            )),
+            stored => Ok(replay(stored)),
+        };
+
+        tracing::info!("Idempotency-Key {} was used before", key.key);
+
+        return Ok(match response {
+            Ok(response) => req.into_response(response),
+            Err(e) => req.error_response(e),
+        });
+    }
+
+    req.set_payload(Payload::from(body));
+    let res = next.call(req).await?;
+
+    let (http_req, response) = res.into_parts();
+    let (head, body) = response.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to read the response"))?;
+
+    let stored = if head.status().is_server_error() {
+        // Nothing was done, so let the client try again.
+        release(&state.db_pool, &key).await
+    } else {
+        let problem = head
+            .error()
+            .and_then(|e| e.as_error::<Error>())
+            .map(|e| serde_json::to_vec(&e.problem(Some(http_req.path().to_string()), None)))
+            .transpose();
+
+        match problem {
+            Ok(problem) => {
+                let stored_body = problem.as_deref().unwrap_or(&body);
+                store(&state.db_pool, &key, &head, stored_body).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    };
+    if let Err(e) = stored {
+        tracing::error!("Failed to store the response for Idempotency-Key {}: {}", key.key, e);
+    }
+
+    Ok(ServiceResponse::new(http_req, head.set_body(body).map_into_boxed_body()))
+}
+
+/// Deletes expired keys every hour until the task is dropped.
+pub async fn expire(pool: PgPool) {
+    loop {
+        match sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= NOW()")
+            .execute(&pool)
+            .await
+        {
+            Ok(result) if result.rows_affected() > 0 => {
+                tracing::info!("Expired {} idempotency keys", result.rows_affected());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to expire idempotency keys: {}", e),
+        }
+
+        tokio::time::sleep(EXPIRE_INTERVAL).await;
+    }
+}
+
+fn parse_key(value: &HeaderValue) -> Result<String> {
+    let key = value.to_str().unwrap_or_default().trim();
+
+    if key.is_empty() || key.len() > MAX_KEY_CHARS || !key.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(Error::Validation(format!(
+            "Idempotency-Key must be 1 to {} printable ASCII characters",
+            MAX_KEY_CHARS
+        )));
+    }
+
+    Ok(key.to_string())
+}
+
+async fn read_body(mut payload: Payload) -> Result<Bytes> {
+    let mut body = BytesMut::new();
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| Error::Validation(format!("Failed to read the request body: {}", e)))?;
+        if body.len() + chunk.len() > transfer::MAX_IMPORT_BYTES {
+            return Err(Error::PayloadTooLarge(format!(
+                "Request body is larger than {} bytes",
+                transfer::MAX_IMPORT_BYTES
+            )));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+/// What makes two requests the same: the method, the target and the body.
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> Vec<u8> {
+    let target = req.uri().path_and_query().map_or(req.path(), |target| target.as_str());
+
+    [req.method().as_str().as_bytes(), b" ", target.as_bytes(), b"\n", body].concat()
+}
+
+/// Claims the key for this request, returning `None` when it was free (or
+/// expired, or abandoned) and the request that used it otherwise.
+async fn claim(pool: &PgPool, key: &Key, fingerprint: &[u8], ttl: Duration) -> Result<Option<StoredRequest>> {
+    loop {
+        let claimed = sqlx::query(
+            "INSERT INTO idempotency_keys (actor, key, request_hash, expires_at) \
+             VALUES ($5, $1, sha256($2), NOW() + $3) \
+             ON CONFLICT (actor, key) DO UPDATE SET request_hash = EXCLUDED.request_hash, status = NULL, \
+                 headers = NULL, body = NULL, created_at = NOW(), expires_at = EXCLUDED.expires_at \
+             WHERE idempotency_keys.expires_at <= NOW() \
+                OR (idempotency_keys.status IS NULL AND idempotency_keys.created_at < NOW() - $4) \
+             RETURNING key",
+        )
+        .bind(&key.key)
+        .bind(fingerprint)
+        .bind(ttl)
+        .bind(ABANDONED_AFTER)
+        .bind(&key.actor)
+        .fetch_optional(pool)
+        .await?;
+
+        if claimed.is_some() {
+            return Ok(None);
+        }
+
+        let stored = sqlx::query_as::<_, StoredRequest>(
+            "SELECT request_hash = sha256($2) AS same_request, status, headers, body \
+             FROM idempotency_keys WHERE key = $1 AND actor = $3",
+        )
+        .bind(&key.key)
+        .bind(fingerprint)
+        .bind(&key.actor)
+        .fetch_optional(pool)
+        .await?;
+
+        // Otherwise the key expired in between; claim it again.
+        if stored.is_some() {
+            return Ok(stored);
+        }
+    }
+}
+
+async fn store(pool: &PgPool, key: &Key, head: &HttpResponse<()>, body: &[u8]) -> Result<()> {
+    let headers: Vec<(String, String)> = head
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    sqlx::query("UPDATE idempotency_keys SET status = $2, headers = $3, body = $4 WHERE key = $1 AND actor = $5")
+        .bind(&key.key)
+        .bind(head.status().as_u16() as i16)
+        .bind(Json(headers))
+        .bind(body)
+        .bind(&key.actor)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn release(pool: &PgPool, key: &Key) -> Result<()> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE key = $1 AND actor = $2")
+        .bind(&key.key)
+        .bind(&key.actor)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+fn replay(stored: StoredRequest) -> HttpResponse {
+    let status = stored
+        .status
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut response = HttpResponse::build(status);
+
+    for (name, value) in stored.headers.map(|Json(headers)| headers).unwrap_or_default() {
+        response.append_header((name, value));
+    }
+    response.insert_header((IDEMPOTENT_REPLAYED, "true"));
+
+    response.body(stored.body.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use actix_web::dev::Service;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::error;
+
+    /// Counts the requests that reach the handlers.
+    #[derive(Clone, Default)]
+    struct Calls {
+        count: Arc<AtomicUsize>,
+        started: Arc<Notify>,
+        finish: Arc<Notify>,
+    }
+
+    impl Calls {
+        fn count(&self) -> usize {
+            self.count.load(Ordering::SeqCst)
+        }
+    }
+
+    async fn create(calls: web::Data<Calls>, body: Bytes) -> HttpResponse {
+        let count = calls.count.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Created()
+            .insert_header(("x-call", count.to_string()))
+            .body(body)
+    }
+
+    /// Fails the first time, as if the database had been unreachable.
+    async fn flaky(calls: web::Data<Calls>) -> Result<HttpResponse> {
+        match calls.count.fetch_add(1, Ordering::SeqCst) {
+            0 => Err(Error::Database(sqlx::Error::PoolTimedOut)),
+            _ => Ok(HttpResponse::Created().finish()),
+        }
+    }
+
+    /// Waits for the test to let it finish.
+    async fn slow(calls: web::Data<Calls>) -> HttpResponse {
+        calls.count.fetch_add(1, Ordering::SeqCst);
+        calls.started.notify_one();
+        calls.finish.notified().await;
+        HttpResponse::Created().finish()
+    }
+
+    async fn app(
+        pool: PgPool,
+        calls: &Calls,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse<BoxBody>, Error = actix_web::Error> {
+        test::init_service(
+            App::new()
+                .app_data(AppState::for_tests(pool).await)
+                .app_data(web::Data::new(calls.clone()))
+                .wrap(from_fn(idempotent_requests))
+                .wrap(from_fn(error::problem_details))
+                .route("/create", web::post().to(create))
+                .route("/flaky", web::post().to(flaky))
+                .route("/slow", web::post().to(slow)),
+        )
+        .await
+    }
+
+    fn post(uri: &str, key: &str) -> TestRequest {
+        TestRequest::post().uri(uri).insert_header((IDEMPOTENCY_KEY, key))
+    }
+
+    async fn problem_code(res: ServiceResponse<BoxBody>) -> String {
+        let problem: serde_json::Value = test::read_body_json(res).await;
+        problem["code"].as_str().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn keys_are_printable_ascii() {
+        assert_eq!(parse_key(&HeaderValue::from_static(" 4f1c-9a ")).unwrap(), "4f1c-9a");
+        assert!(parse_key(&HeaderValue::from_static("")).is_err());
+        assert!(parse_key(&HeaderValue::from_static("two words")).is_err());
+        assert!(parse_key(&HeaderValue::from_str(&"k".repeat(MAX_KEY_CHARS + 1)).unwrap()).is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn repeated_requests_replay_the_first_response(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        let first = test::call_service(&app, post("/create", "k1").set_payload("hello").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        assert_eq!(test::read_body(first).await, "hello");
+
+        let again = test::call_service(&app, post("/create", "k1").set_payload("hello").to_request()).await;
+        assert_eq!(again.status(), StatusCode::CREATED);
+        assert_eq!(again.headers().get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert_eq!(again.headers().get("x-call").unwrap(), "1");
+        assert_eq!(test::read_body(again).await, "hello");
+        assert_eq!(calls.count(), 1);
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn keys_cannot_be_reused_for_another_request(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        test::call_service(&app, post("/create", "k1").set_payload("hello").to_request()).await;
+        let other = test::call_service(&app, post("/create", "k1").set_payload("bye").to_request()).await;
+
+        assert_eq!(other.status(), StatusCode::CONFLICT);
+        assert_eq!(problem_code(other).await, "idempotency_key_reused");
+        assert_eq!(calls.count(), 1);
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn keys_in_flight_are_conflicts(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        let first = test::call_service(&app, post("/slow", "k1").to_request());
+        let second = async {
+            calls.started.notified().await;
+            let res = test::call_service(&app, post("/slow", "k1").to_request()).await;
+            calls.finish.notify_one();
+            res
+        };
+        let (first, second) = futures_util::join!(first, second);
+
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        assert_eq!(problem_code(second).await, "conflict");
+        assert_eq!(calls.count(), 1);
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn keys_are_released_after_server_errors(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        let failed = test::call_service(&app, post("/flaky", "k1").to_request()).await;
+        assert_eq!(failed.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let retried = test::call_service(&app, post("/flaky", "k1").to_request()).await;
+        assert_eq!(retried.status(), StatusCode::CREATED);
+        assert!(retried.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        assert_eq!(calls.count(), 2);
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn keys_are_scoped_to_the_actor(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        for actor in ["alice", "bob"] {
+            let req = post("/create", "k1").insert_header(("x-actor", actor)).set_payload(actor);
+            let res = test::call_service(&app, req.to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert!(res.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        }
+        assert_eq!(calls.count(), 2);
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn oversized_bodies_are_rejected(pool: PgPool) {
+        let calls = Calls::default();
+        let app = app(pool, &calls).await;
+
+        let body = vec![b'x'; transfer::MAX_IMPORT_BYTES + 1];
+        let res = test::call_service(&app, post("/create", "k1").set_payload(body).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(problem_code(res).await, "payload_too_large");
+        assert_eq!(calls.count(), 0);
+    }
+}
@@ -1,184 +1,755 @@
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_cors::Cors;
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::http::header::{self, HeaderName};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPool;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::FromRow;
+use todo_events::{nats, Priority, TodoAction, TodoSnapshot};
+use tokio::sync::Notify;
+
+pub mod batch;
+pub mod error;
+pub mod etag;
+pub mod history;
+pub mod idempotency;
+pub mod listing;
+pub mod lists;
+pub mod migrations;
+pub mod outbox;
+pub mod transfer;
+pub mod trash;
+pub mod validation;
+
+pub use error::{Error, Result};
+pub use etag::IfMatch;
+pub use history::Actor;
+pub use listing::{fetch_page, ListParams, Page};
+pub use lists::TodoList;
+pub use migrations::{pending_migrations, run_migrations};
+pub use validation::ContentPolicy;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Todo {
     pub id: i32,
     pub content: String,
     pub done: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub version: i32,
+    #[sqlx(try_from = "String")]
+    pub priority: Priority,
+    pub due_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub list_id: i32,
+    /// Set while the todo is in the trash.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Todo {
+    pub fn snapshot(&self) -> TodoSnapshot {
+        TodoSnapshot {
+            id: self.id,
+            content: self.content.clone(),
+            done: self.done,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            completed_at: self.completed_at,
+            version: self.version,
+            priority: self.priority,
+            due_at: self.due_at,
+            tags: self.tags.clone(),
+            list_id: Some(self.list_id),
+            deleted_at: self.deleted_at,
+        }
+    }
 }
 
+/// Columns selected into a [`Todo`], in the order the struct declares them.
+/// Tags are sorted by code point, as [`validation::check_tags`] sorts them.
+pub(crate) const TODO_COLUMNS: &str = "id, content, done, created_at, updated_at, completed_at, version, \
+     priority, due_at, list_id, deleted_at, \
+     ARRAY(SELECT tags.name FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id \
+           WHERE todo_tags.todo_id = todos.id ORDER BY tags.name COLLATE \"C\") AS tags";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateTodo {
     pub content: String,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A partial update. `due_at` distinguishes a missing field (keep) from
+/// `null` (clear); `tags`, when given, replaces every tag; `list_id` moves
+/// the todo to another list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatchTodo {
+    pub content: Option<String>,
+    pub done: Option<bool>,
+    pub priority: Option<Priority>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<Option<DateTime<Utc>>>,
+    pub tags: Option<Vec<String>>,
+    pub list_id: Option<i32>,
+}
+
+impl PatchTodo {
+    fn is_empty(&self) -> bool {
+        self.content.is_none()
+            && self.done.is_none()
+            && self.priority.is_none()
+            && self.due_at.is_none()
+            && self.tags.is_none()
+            && self.list_id.is_none()
+    }
+}
+
+/// Deserializes a field that is present, `null` included, as `Some`.
+fn present<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Clone)]
 struct AppState {
     db_pool: PgPool,
     nats_client: async_nats::Client,
+    nats_status: nats::Status,
+    outbox_wakeup: Arc<Notify>,
+    content_policy: ContentPolicy,
+    actor_header: HeaderName,
+    idempotency_ttl: Duration,
 }
 
-async fn health_check() -> HttpResponse {
-    HttpResponse::Ok()
+impl AppState {
+    fn new(
+        db_pool: PgPool,
+        (nats_client, nats_status): (async_nats::Client, nats::Status),
+        outbox_wakeup: Arc<Notify>,
+        settings: Settings,
+    ) -> Self {
+        Self {
+            db_pool,
+            nats_client,
+            nats_status,
+            outbox_wakeup,
+            content_policy: settings.content_policy,
+            actor_header: settings.actor_header,
+            idempotency_ttl: settings.idempotency_ttl,
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for handler tests. NATS is never reachable, so events stay in
+    /// the outbox.
+    async fn for_tests(db_pool: PgPool) -> web::Data<Self> {
+        let nats_client = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .expect("Failed to create the NATS client");
+
+        web::Data::new(Self::new(
+            db_pool,
+            (nats_client, nats::Status::default()),
+            Arc::new(Notify::new()),
+            Settings::default(),
+        ))
+    }
+}
+
+/// Reports the NATS connection but stays healthy while it is down, since
+/// events wait in the outbox until it is back. A closed client never comes
+/// back, so that is unhealthy.
+async fn health_check(state: web::Data<AppState>) -> HttpResponse {
+    let closed = state.nats_status.is_closed() || state.nats_status.gave_up();
+    let nats = match state.nats_client.connection_state() {
+        _ if closed => "closed",
+        async_nats::connection::State::Connected => "connected",
+        async_nats::connection::State::Disconnected => "disconnected",
+        async_nats::connection::State::Pending => "connecting",
+    };
+
+    let (mut response, status) = match closed {
+        true => (HttpResponse::ServiceUnavailable(), "unavailable"),
+        false => (HttpResponse::Ok(), "ok"),
+    };
+
+    response
         .content_type("application/json; charset=utf-8")
-        .body(r#"{"status":"ok"}"#)
-}
-
-async fn get_todos(state: web::Data<AppState>) -> HttpResponse {
-    match fetch_todos(&state.db_pool).await {
-        Ok(todos) => HttpResponse::Ok()
-            .content_type("application/json; charset=utf-8")
-            .json(todos),
-        Err(e) => {
-            tracing::error!("Failed to fetch todos: {}", e);
-            HttpResponse::InternalServerError()
-                .content_type("application/json; charset=utf-8")
-                .json(serde_json::json!({
-                    "error": "Failed to fetch todos"
-                }))
+        .body(serde_json::json!({ "status": status, "nats": nats }).to_string())
+}
+
+/// Lists the default list's todos.
+async fn get_todos(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    params: web::Query<ListParams>,
+) -> Result<HttpResponse> {
+    let list_id = lists::default_list_id(&state.db_pool).await?;
+
+    todo_page_response(&state, &req, params.into_inner(), list_id).await
+}
+
+async fn get_list_todos(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+    params: web::Query<ListParams>,
+) -> Result<HttpResponse> {
+    let list_id = id.into_inner();
+
+    if !lists::list_exists(&state.db_pool, list_id).await? {
+        return Err(Error::ListNotFound);
+    }
+
+    todo_page_response(&state, &req, params.into_inner(), list_id).await
+}
+
+async fn todo_page_response(
+    state: &AppState,
+    req: &HttpRequest,
+    params: ListParams,
+    list_id: i32,
+) -> Result<HttpResponse> {
+    listing::validate(&params).map_err(|message| Error::Validation(message.to_string()))?;
+
+    let page = match params.as_of {
+        Some(as_of) => Page {
+            todos: history::todos_as_of(&state.db_pool, list_id, as_of).await?,
+            next_cursor: None,
+        },
+        None => fetch_page(&state.db_pool, list_id, &params).await?,
+    };
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/json; charset=utf-8");
+
+    let body = serde_json::to_vec(&page.todos)?;
+    let list_etag = etag::list_etag(&body);
+
+    if etag::if_none_match(req, &list_etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, list_etag))
+            .finish());
+    }
+
+    response.insert_header((header::ETAG, list_etag));
+
+    if let Some(next_cursor) = page.next_cursor {
+        let next = ListParams {
+            cursor: Some(next_cursor),
+            ..params
+        };
+        if let Ok(query) = serde_urlencoded::to_string(&next) {
+            response.insert_header((
+                header::LINK,
+                format!("<{}?{}>; rel=\"next\"", req.path(), query),
+            ));
         }
     }
+
+    Ok(response.body(body))
 }
 
+/// Adds a todo to the default list.
 async fn create_todo(
     state: web::Data<AppState>,
+    req: HttpRequest,
     new_todo: web::Json<CreateTodo>,
-) -> HttpResponse {
-    let content = new_todo.content.trim();
-
-    tracing::info!("Received todo creation request: \"{}\" (length: {} chars)", content, content.len());
-
-    if content.is_empty() {
-        tracing::warn!("Rejected todo: content is empty");
-        return HttpResponse::BadRequest()
-            .content_type("application/json; charset=utf-8")
-            .json(serde_json::json!({
-                "error": "Todo content cannot be empty"
-            }));
-    }
-
-    if content.len() > 140 {
-        tracing::warn!(
-            "Rejected todo: content exceeds 140 character limit (length: {} chars): \"{}\"",
-            content.len(),
-            content
-        );
-        return HttpResponse::BadRequest()
-            .content_type("application/json; charset=utf-8")
-            .json(serde_json::json!({
-                "error": "Todo content must be 140 characters or less"
-            }));
-    }
-
-    match insert_todo(&state.db_pool, content).await {
-        Ok(todo) => {
-            tracing::info!("Created new todo with id {}: {}", todo.id, todo.content);
-
-            let message = serde_json::json!({
-                "action": "created",
-                "todo": todo
-            });
-            if let Err(e) = state.nats_client.publish("todo.events", message.to_string().into()).await {
-                tracing::warn!("Failed to publish todo creation to NATS: {}", e);
-            }
-
-            HttpResponse::Created()
-                .content_type("application/json; charset=utf-8")
-                .json(todo)
-        }
-        Err(e) => {
-            tracing::error!("Failed to create todo: {}", e);
-            HttpResponse::InternalServerError()
-                .content_type("application/json; charset=utf-8")
-                .json(serde_json::json!({
-                    "error": "Failed to create todo"
-                }))
-        }
-    }
+) -> Result<HttpResponse> {
+    add_todo(&state, &req, None, new_todo.into_inner()).await
 }
 
-async fn fetch_todos(pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
-    let todos = sqlx::query_as::<_, (i32, String, bool)>(
-        "SELECT id, content, done FROM todos ORDER BY created_at DESC"
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|(id, content, done)| Todo { id, content, done })
-    .collect();
+async fn create_list_todo(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+    new_todo: web::Json<CreateTodo>,
+) -> Result<HttpResponse> {
+    add_todo(&state, &req, Some(id.into_inner()), new_todo.into_inner()).await
+}
+
+/// Adds a todo to the given list, or to the default list.
+async fn add_todo(
+    state: &AppState,
+    req: &HttpRequest,
+    list_id: Option<i32>,
+    new_todo: CreateTodo,
+) -> Result<HttpResponse> {
+    let actor = Actor::from_request(req, &state.actor_header)?;
+
+    tracing::info!("Received todo creation request: \"{}\"", new_todo.content);
+
+    let new_todo = check_new_todo(&state.content_policy, new_todo)?;
+
+    let mut tx = state.db_pool.begin().await?;
+    let todo = insert_todo(&mut tx, &actor, list_id, &new_todo)
+        .await?
+        .ok_or(Error::ListNotFound)?;
+    tx.commit().await?;
 
-    Ok(todos)
+    tracing::info!("Created new todo with id {}: {}", todo.id, todo.content);
+
+    state.outbox_wakeup.notify_one();
+
+    Ok(todo_response(HttpResponse::Created(), &todo))
 }
 
-async fn insert_todo(pool: &PgPool, content: &str) -> Result<Todo, sqlx::Error> {
-    let row = sqlx::query_as::<_, (i32, String, bool)>(
-        "INSERT INTO todos (content) VALUES ($1) RETURNING id, content, done"
-    )
-    .bind(content)
-    .fetch_one(pool)
-    .await?;
+/// Validates and normalizes a new todo, returning the todo to insert.
+pub(crate) fn check_new_todo(policy: &ContentPolicy, new_todo: CreateTodo) -> Result<CreateTodo> {
+    let content = policy.check(&new_todo.content).inspect_err(|e| {
+        tracing::warn!("Rejected todo: {}", e);
+    })?;
+    let tags = validation::check_tags(&new_todo.tags).inspect_err(|e| {
+        tracing::warn!("Rejected todo: {}", e);
+    })?;
 
-    Ok(Todo {
-        id: row.0,
-        content: row.1,
-        done: row.2,
-    })
+    Ok(CreateTodo { content, tags, ..new_todo })
+}
+
+/// Inserts an already validated todo into the given list, or the default
+/// one. Returns `None` when the list does not exist. Must be called on a
+/// transaction.
+pub(crate) async fn insert_todo(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    list_id: Option<i32>,
+    new_todo: &CreateTodo,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let Some(mut todo) = sqlx::query_as::<_, Todo>(&format!(
+        "INSERT INTO todos (content, priority, due_at, list_id) \
+         SELECT $1, $2, $3, id FROM lists \
+         WHERE id = COALESCE($4, (SELECT id FROM lists WHERE is_default)) \
+         RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(&new_todo.content)
+    .bind(new_todo.priority.as_str())
+    .bind(new_todo.due_at)
+    .bind(list_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    set_tags(conn, todo.id, &new_todo.tags).await?;
+    todo.tags = new_todo.tags.clone();
+
+    record_change(conn, actor, TodoAction::Created, &todo, None).await?;
+
+    Ok(Some(todo))
 }
 
 async fn update_todo(
     state: web::Data<AppState>,
+    req: HttpRequest,
     id: web::Path<i32>,
-) -> HttpResponse {
+) -> Result<HttpResponse> {
     let todo_id = id.into_inner();
+    let if_match = IfMatch::from_request(&req, todo_id);
+    let actor = Actor::from_request(&req, &state.actor_header)?;
 
     tracing::info!("Marking todo {} as done", todo_id);
 
-    match mark_todo_done(&state.db_pool, todo_id).await {
-        Ok(todo) => {
-            tracing::info!("Successfully marked todo {} as done", todo.id);
-
-            let message = serde_json::json!({
-                "action": "updated",
-                "todo": todo
-            });
-            if let Err(e) = state.nats_client.publish("todo.events", message.to_string().into()).await {
-                tracing::warn!("Failed to publish todo update to NATS: {}", e);
-            }
-
-            HttpResponse::Ok()
-                .content_type("application/json; charset=utf-8")
-                .json(todo)
-        }
-        Err(e) => {
-            tracing::error!("Failed to update todo {}: {}", todo_id, e);
-            HttpResponse::NotFound()
-                .content_type("application/json; charset=utf-8")
-                .json(serde_json::json!({
-                    "error": "Todo not found"
-                }))
+    let Some(todo) = mark_todo_done(&state.db_pool, &actor, todo_id, if_match.versions()).await? else {
+        return Err(not_found_or_precondition_failed(&state.db_pool, todo_id, &if_match).await);
+    };
+
+    tracing::info!("Successfully marked todo {} as done", todo.id);
+
+    state.outbox_wakeup.notify_one();
+
+    Ok(todo_response(HttpResponse::Ok(), &todo))
+}
+
+async fn mark_todo_done(
+    pool: &PgPool,
+    actor: &Actor,
+    id: i32,
+    versions: Option<&[i32]>,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let Some(previous) = lock_todo(&mut tx, id, versions).await? else {
+        return Ok(None);
+    };
+
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos SET done = TRUE WHERE id = $1 RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_change(&mut tx, actor, TodoAction::Updated, &todo, Some(&previous)).await?;
+    tx.commit().await?;
+
+    Ok(Some(todo))
+}
+
+async fn get_todo(
+    state: web::Data<AppState>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let todo_id = id.into_inner();
+
+    match fetch_todo(&state.db_pool, todo_id).await? {
+        Some(todo) => Ok(todo_response(HttpResponse::Ok(), &todo)),
+        None => Err(Error::TodoNotFound),
+    }
+}
+
+async fn patch_todo(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+    patch: web::Json<PatchTodo>,
+) -> Result<HttpResponse> {
+    let todo_id = id.into_inner();
+    let if_match = IfMatch::from_request(&req, todo_id);
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+    let patch = check_patch(&state.content_policy, patch.into_inner())?;
+
+    tracing::info!("Patching todo {}", todo_id);
+
+    let mut tx = state.db_pool.begin().await?;
+    let Some(todo) = apply_todo_patch(&mut tx, &actor, todo_id, &patch, if_match.versions()).await? else {
+        return Err(not_found_or_precondition_failed(&mut *tx, todo_id, &if_match).await);
+    };
+    tx.commit().await?;
+
+    tracing::info!("Successfully patched todo {}", todo.id);
+
+    state.outbox_wakeup.notify_one();
+
+    Ok(todo_response(HttpResponse::Ok(), &todo))
+}
+
+async fn delete_todo(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let todo_id = id.into_inner();
+    let if_match = IfMatch::from_request(&req, todo_id);
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+
+    tracing::info!("Deleting todo {}", todo_id);
+
+    let mut tx = state.db_pool.begin().await?;
+    let Some(todo) = remove_todo(&mut tx, &actor, todo_id, if_match.versions()).await? else {
+        return Err(not_found_or_precondition_failed(&mut *tx, todo_id, &if_match).await);
+    };
+    tx.commit().await?;
+
+    tracing::info!("Successfully deleted todo {}", todo.id);
+
+    state.outbox_wakeup.notify_one();
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn get_trash(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let todos = trash::fetch_trash(&state.db_pool).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(todos))
+}
+
+async fn restore_todo(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let todo_id = id.into_inner();
+    let if_match = IfMatch::from_request(&req, todo_id);
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+
+    tracing::info!("Restoring todo {}", todo_id);
+
+    let Some(todo) = restore_todo_from_trash(&state.db_pool, &actor, todo_id, if_match.versions()).await? else {
+        return Err(match trash::fetch_trashed(&state.db_pool, todo_id).await? {
+            Some(trashed) if if_match != IfMatch::Any => Error::PreconditionFailed {
+                etag: etag::todo_etag(&trashed),
+            },
+            _ => Error::TodoNotFound,
+        });
+    };
+
+    tracing::info!("Successfully restored todo {}", todo.id);
+
+    state.outbox_wakeup.notify_one();
+
+    Ok(todo_response(HttpResponse::Ok(), &todo))
+}
+
+async fn get_todo_history(state: web::Data<AppState>, id: web::Path<i32>) -> Result<HttpResponse> {
+    let todo_id = id.into_inner();
+
+    let entries = history::fetch_history(&state.db_pool, todo_id).await?;
+    if entries.is_empty() && fetch_todo(&state.db_pool, todo_id).await?.is_none() {
+        return Err(Error::TodoNotFound);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(entries))
+}
+
+fn todo_response(mut response: HttpResponseBuilder, todo: &Todo) -> HttpResponse {
+    response
+        .content_type("application/json; charset=utf-8")
+        .insert_header((header::ETAG, etag::todo_etag(todo)))
+        .json(todo)
+}
+
+/// Explains why a conditional mutation matched no row: either the todo does
+/// not exist, or it does but its current version failed `If-Match`.
+pub(crate) async fn not_found_or_precondition_failed(
+    executor: impl PgExecutor<'_>,
+    id: i32,
+    if_match: &IfMatch,
+) -> Error {
+    if *if_match == IfMatch::Any {
+        return Error::TodoNotFound;
+    }
+
+    match fetch_todo(executor, id).await {
+        Ok(Some(todo)) => Error::PreconditionFailed {
+            etag: etag::todo_etag(&todo),
+        },
+        Ok(None) => Error::TodoNotFound,
+        Err(e) => e.into(),
+    }
+}
+
+/// Fetches a todo that is not in the trash.
+async fn fetch_todo(executor: impl PgExecutor<'_>, id: i32) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos WHERE id = $1 AND deleted_at IS NULL"
+    ))
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Validates and normalizes a partial update, returning the patch to apply.
+pub(crate) fn check_patch(policy: &ContentPolicy, patch: PatchTodo) -> Result<PatchTodo> {
+    if patch.is_empty() {
+        return Err(Error::Validation(
+            "Nothing to update: provide content, done, priority, due_at, tags and/or list_id".to_string(),
+        ));
+    }
+
+    let content = patch
+        .content
+        .as_deref()
+        .map(|content| policy.check(content))
+        .transpose()
+        .inspect_err(|e| tracing::warn!("Rejected todo patch: {}", e))?;
+    let tags = patch
+        .tags
+        .as_deref()
+        .map(validation::check_tags)
+        .transpose()
+        .inspect_err(|e| tracing::warn!("Rejected todo patch: {}", e))?;
+
+    Ok(PatchTodo { content, tags, ..patch })
+}
+
+/// Applies an already validated partial update, recording it as `reopened`
+/// when it clears `done` and as `updated` otherwise. Returns `None` when no
+/// todo with the given id (and one of the given versions, if any) exists.
+/// Must be called on a transaction.
+pub(crate) async fn apply_todo_patch(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    id: i32,
+    patch: &PatchTodo,
+    versions: Option<&[i32]>,
+) -> Result<Option<Todo>> {
+    let Some(previous) = lock_todo(conn, id, versions).await? else {
+        return Ok(None);
+    };
+
+    if let Some(list_id) = patch.list_id {
+        if !lists::lock_list_key(conn, list_id).await? {
+            return Err(Error::ListNotFound);
         }
     }
+
+    // Always updates the row, even for a tags-only patch, so the version
+    // moves on.
+    let mut todo = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos SET content = COALESCE($2, content), done = COALESCE($3, done), \
+         priority = COALESCE($4, priority), due_at = CASE WHEN $5 THEN $6 ELSE due_at END, \
+         list_id = COALESCE($7, list_id) \
+         WHERE id = $1 RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(id)
+    .bind(patch.content.as_deref())
+    .bind(patch.done)
+    .bind(patch.priority.as_ref().map(Priority::as_str))
+    .bind(patch.due_at.is_some())
+    .bind(patch.due_at.flatten())
+    .bind(patch.list_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if let Some(tags) = &patch.tags {
+        set_tags(conn, id, tags).await?;
+        todo.tags = tags.clone();
+    }
+
+    let action = if previous.done && !todo.done {
+        TodoAction::Reopened
+    } else {
+        TodoAction::Updated
+    };
+    record_change(conn, actor, action, &todo, Some(&previous)).await?;
+
+    Ok(Some(todo))
+}
+
+/// Records a change in the history and queues its event. Must be called on
+/// the transaction making the change.
+pub(crate) async fn record_change(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    action: TodoAction,
+    todo: &Todo,
+    previous: Option<&Todo>,
+) -> Result<(), sqlx::Error> {
+    history::record(conn, actor, action, todo, previous).await?;
+    outbox::enqueue(conn, action, todo, previous).await?;
+
+    Ok(())
+}
+
+/// Locks the todo row for the rest of the transaction, returning `None` when
+/// it does not exist, is (or is not, with `trashed`) in the trash, or its
+/// version is not one of `versions`.
+async fn lock_todo(
+    conn: &mut PgConnection,
+    id: i32,
+    versions: Option<&[i32]>,
+) -> Result<Option<Todo>, sqlx::Error> {
+    lock_todo_in(conn, id, versions, false).await
+}
+
+async fn lock_todo_in(
+    conn: &mut PgConnection,
+    id: i32,
+    versions: Option<&[i32]>,
+    trashed: bool,
+) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos \
+         WHERE id = $1 AND ($2::INTEGER[] IS NULL OR version = ANY($2)) \
+         AND (deleted_at IS NOT NULL) = $3 \
+         FOR UPDATE"
+    ))
+    .bind(id)
+    .bind(versions)
+    .bind(trashed)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Replaces the todo's tags with `tags`, which must already be checked.
+pub(crate) async fn set_tags(conn: &mut PgConnection, todo_id: i32, tags: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM todo_tags WHERE todo_id = $1")
+        .bind(todo_id)
+        .execute(&mut *conn)
+        .await?;
+
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query("INSERT INTO tags (name) SELECT unnest($1::TEXT[]) ON CONFLICT (name) DO NOTHING")
+        .bind(tags)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("INSERT INTO todo_tags (todo_id, tag_id) SELECT $1, id FROM tags WHERE name = ANY($2)")
+        .bind(todo_id)
+        .bind(tags)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
 }
 
-async fn mark_todo_done(pool: &PgPool, id: i32) -> Result<Todo, sqlx::Error> {
-    let row = sqlx::query_as::<_, (i32, String, bool)>(
-        "UPDATE todos SET done = TRUE WHERE id = $1 RETURNING id, content, done"
-    )
+/// Moves a todo to the trash. Returns `None` when no todo with the given id
+/// (and one of the given versions, if any) exists. Must be called on a
+/// transaction.
+pub(crate) async fn remove_todo(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    id: i32,
+    versions: Option<&[i32]>,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let Some(previous) = lock_todo(conn, id, versions).await? else {
+        return Ok(None);
+    };
+
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos SET deleted_at = NOW() WHERE id = $1 RETURNING {TODO_COLUMNS}"
+    ))
     .bind(id)
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await?;
 
-    Ok(Todo {
-        id: row.0,
-        content: row.1,
-        done: row.2,
-    })
+    record_change(conn, actor, TodoAction::Deleted, &todo, Some(&previous)).await?;
+
+    Ok(Some(todo))
+}
+
+/// Takes a todo out of the trash. Returns `None` when no trashed todo with
+/// the given id (and one of the given versions, if any) exists.
+async fn restore_todo_from_trash(
+    pool: &PgPool,
+    actor: &Actor,
+    id: i32,
+    versions: Option<&[i32]>,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let Some(previous) = lock_todo_in(&mut tx, id, versions, true).await? else {
+        return Ok(None);
+    };
+
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos SET deleted_at = NULL WHERE id = $1 RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_change(&mut tx, actor, TodoAction::Restored, &todo, Some(&previous)).await?;
+    tx.commit().await?;
+
+    Ok(Some(todo))
 }
 
 pub async fn connect_to_database() -> Result<PgPool, sqlx::Error> {
@@ -208,32 +779,118 @@ pub async fn connect_to_database() -> Result<PgPool, sqlx::Error> {
     Ok(pool)
 }
 
-pub async fn connect_to_nats() -> Result<async_nats::Client, async_nats::ConnectError> {
-    let nats_url = std::env::var("NATS_URL").unwrap_or_else(|_| "nats://my-nats:4222".to_string());
-
-    tracing::info!("Connecting to NATS at {}", nats_url);
+/// Connects with the settings from the environment (see
+/// [`todo_events::nats`]). Returns at once and keeps connecting in the
+/// background, so the API is served while NATS is unreachable; the outbox
+/// holds events until the relay gets through.
+pub async fn connect_to_nats() -> Result<(async_nats::Client, nats::Status), nats::Error> {
+    let config = nats::ConnectConfig::from_env()?;
+    nats::connect_in_background(&config).await
+}
 
-    let client = async_nats::connect(&nats_url).await?;
+/// What [`run`] can be configured with, each read from the environment by
+/// the `*_from_env` function of its module.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub content_policy: ContentPolicy,
+    /// Header naming who made a change.
+    pub actor_header: HeaderName,
+    /// How long deleted todos stay in the trash.
+    pub trash_retention: Duration,
+    /// How long responses to `Idempotency-Key` requests are kept.
+    pub idempotency_ttl: Duration,
+}
 
-    tracing::info!("Connected to NATS successfully");
+impl Settings {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            content_policy: ContentPolicy::from_env()?,
+            actor_header: history::actor_header_from_env()?,
+            trash_retention: trash::retention_from_env()?,
+            idempotency_ttl: idempotency::ttl_from_env()?,
+        })
+    }
+}
 
-    Ok(client)
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            content_policy: ContentPolicy::default(),
+            actor_header: HeaderName::from_static(history::DEFAULT_ACTOR_HEADER),
+            trash_retention: trash::DEFAULT_RETENTION,
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+        }
+    }
 }
 
-pub fn run(listener: TcpListener, pool: PgPool, nats_client: async_nats::Client) -> Result<Server, std::io::Error> {
-    let state = web::Data::new(AppState { db_pool: pool, nats_client });
+pub fn run(
+    listener: TcpListener,
+    pool: PgPool,
+    (nats_client, nats_status): (async_nats::Client, nats::Status),
+    settings: Settings,
+) -> Result<Server, std::io::Error> {
+    let outbox_wakeup = Arc::new(Notify::new());
+    tokio::spawn(outbox::relay(pool.clone(), nats_client.clone(), outbox_wakeup.clone()));
+    tokio::spawn(trash::purge(pool.clone(), settings.trash_retention, outbox_wakeup.clone()));
+    tokio::spawn(idempotency::expire(pool.clone()));
+
+    let state = web::Data::new(AppState::new(pool, (nats_client, nats_status), outbox_wakeup, settings));
 
     let server = HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
             .app_data(state.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| Error::from(e).into()))
+            .app_data(web::PathConfig::default().error_handler(|e, _| Error::Validation(e.to_string()).into()))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| Error::Validation(e.to_string()).into()))
+            .wrap(from_fn(idempotency::idempotent_requests))
+            .wrap(from_fn(error::problem_details))
             .wrap(cors)
             .wrap(tracing_actix_web::TracingLogger::default())
-            .route("/todos", web::get().to(get_todos))
-            .route("/todos", web::post().to(create_todo))
-            .route("/todos/{id}", web::put().to(update_todo))
-            .route("/healthz", web::get().to(health_check))
+            // One resource per path, so a known path with the wrong method
+            // gets a 405 rather than the 404 for unknown paths.
+            .service(
+                web::resource("/todos")
+                    .route(web::get().to(get_todos))
+                    .route(web::post().to(create_todo)),
+            )
+            .service(web::resource("/todos:batch").route(web::post().to(batch::batch_todos)))
+            // Before `/todos/{id}`, which would take these for an id.
+            .service(web::resource("/todos/trash").route(web::get().to(get_trash)))
+            .service(web::resource("/todos/export").route(web::get().to(transfer::export_todos)))
+            .service(
+                web::resource("/todos/import")
+                    .app_data(web::PayloadConfig::new(transfer::MAX_IMPORT_BYTES))
+                    .route(web::post().to(transfer::import_todos)),
+            )
+            .service(
+                web::resource("/todos/{id}")
+                    .route(web::get().to(get_todo))
+                    .route(web::put().to(update_todo))
+                    .route(web::patch().to(patch_todo))
+                    .route(web::delete().to(delete_todo)),
+            )
+            .service(web::resource("/todos/{id}/history").route(web::get().to(get_todo_history)))
+            .service(web::resource("/todos/{id}/restore").route(web::post().to(restore_todo)))
+            .service(
+                web::resource("/lists")
+                    .route(web::get().to(lists::get_lists))
+                    .route(web::post().to(lists::create_list)),
+            )
+            .service(
+                web::resource("/lists/{id}")
+                    .route(web::get().to(lists::get_list))
+                    .route(web::patch().to(lists::patch_list))
+                    .route(web::delete().to(lists::delete_list)),
+            )
+            .service(
+                web::resource("/lists/{id}/todos")
+                    .route(web::get().to(get_list_todos))
+                    .route(web::post().to(create_list_todo)),
+            )
+            .service(web::resource("/healthz").route(web::get().to(health_check)))
+            .default_service(web::to(error::route_not_found))
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,329 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, QueryBuilder};
+use todo_events::Priority;
+
+use crate::validation::tag_key;
+use crate::{Todo, TODO_COLUMNS};
+
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    Id,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Query parameters accepted by `GET /todos`. Without `limit` every matching
+/// todo is returned, which keeps the endpoint compatible with older clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListParams {
+    pub done: Option<bool>,
+    pub q: Option<String>,
+    pub priority: Option<Priority>,
+    /// Only todos with this tag.
+    pub tag: Option<String>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub due_after: Option<DateTime<Utc>>,
+    /// Only todos past their due date and not done, or only the others.
+    pub overdue: Option<bool>,
+    /// The todos as they were at this time, rebuilt from their history.
+    /// Cannot be combined with the other parameters.
+    pub as_of: Option<DateTime<Utc>>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub todos: Vec<Todo>,
+    pub next_cursor: Option<String>,
+}
+
+/// Position of the last row on a page. `key` is the sort column value
+/// (microseconds since the Unix epoch for `created_at`, the id itself for `id`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cursor {
+    sort: SortField,
+    order: SortOrder,
+    key: i64,
+    id: i32,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}:{}:{}",
+            sort_field_name(self.sort),
+            sort_order_name(self.order),
+            self.key,
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let mut parts = raw.split(':');
+
+        let sort = match parts.next()? {
+            "created_at" => SortField::CreatedAt,
+            "id" => SortField::Id,
+            _ => return None,
+        };
+        let order = match parts.next()? {
+            "asc" => SortOrder::Asc,
+            "desc" => SortOrder::Desc,
+            _ => return None,
+        };
+        let key = parts.next()?.parse().ok()?;
+        let id = parts.next()?.parse().ok()?;
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self { sort, order, key, id })
+    }
+}
+
+fn sort_field_name(sort: SortField) -> &'static str {
+    match sort {
+        SortField::CreatedAt => "created_at",
+        SortField::Id => "id",
+    }
+}
+
+fn sort_order_name(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => "asc",
+        SortOrder::Desc => "desc",
+    }
+}
+
+fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Checks the parameters that serde cannot, returning a client-facing message.
+pub fn validate(params: &ListParams) -> Result<(), &'static str> {
+    if params.as_of.is_some() {
+        let only_as_of = ListParams {
+            as_of: None,
+            ..params.clone()
+        };
+        if only_as_of != ListParams::default() {
+            return Err("as_of cannot be combined with other parameters");
+        }
+    }
+
+    if let Some(limit) = params.limit {
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err("limit must be between 1 and 100");
+        }
+    }
+
+    if let (Some(after), Some(before)) = (params.due_after, params.due_before) {
+        if after >= before {
+            return Err("due_after must be earlier than due_before");
+        }
+    }
+
+    if let Some(cursor) = &params.cursor {
+        let sort = params.sort.unwrap_or_default();
+        let order = params.order.unwrap_or_default();
+
+        match Cursor::decode(cursor) {
+            Some(c) if c.sort == sort && c.order == order => {}
+            _ => return Err("Invalid cursor"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetches a page of the todos in the given list.
+pub async fn fetch_page(pool: &PgPool, list_id: i32, params: &ListParams) -> Result<Page, sqlx::Error> {
+    let sort = params.sort.unwrap_or_default();
+    let order = params.order.unwrap_or_default();
+    let cursor = params.cursor.as_deref().and_then(Cursor::decode);
+
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "SELECT {TODO_COLUMNS} FROM todos WHERE deleted_at IS NULL AND list_id = "
+    ));
+    query.push_bind(list_id);
+
+    if let Some(done) = params.done {
+        query.push(" AND done = ").push_bind(done);
+    }
+
+    if let Some(q) = params.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        query
+            .push(" AND content ILIKE ")
+            .push_bind(format!("%{}%", escape_like(q)))
+            .push(" ESCAPE '\\'");
+    }
+
+    if let Some(priority) = params.priority {
+        query.push(" AND priority = ").push_bind(priority.as_str());
+    }
+
+    if let Some(tag) = params.tag.as_deref().map(tag_key).filter(|tag| !tag.is_empty()) {
+        query
+            .push(
+                " AND EXISTS (SELECT 1 FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id \
+                 WHERE todo_tags.todo_id = todos.id AND tags.name = ",
+            )
+            .push_bind(tag)
+            .push(")");
+    }
+
+    if let Some(due_before) = params.due_before {
+        query.push(" AND due_at < ").push_bind(due_before);
+    }
+
+    if let Some(due_after) = params.due_after {
+        query.push(" AND due_at >= ").push_bind(due_after);
+    }
+
+    match params.overdue {
+        Some(true) => {
+            query.push(" AND due_at < NOW() AND NOT done");
+        }
+        Some(false) => {
+            query.push(" AND (due_at IS NULL OR due_at >= NOW() OR done)");
+        }
+        None => {}
+    }
+
+    let comparison = match order {
+        SortOrder::Asc => " > ",
+        SortOrder::Desc => " < ",
+    };
+
+    if let Some(cursor) = cursor {
+        match sort {
+            SortField::CreatedAt => {
+                query
+                    .push(" AND (created_at, id)")
+                    .push(comparison)
+                    .push("(")
+                    .push_bind(DateTime::from_timestamp_micros(cursor.key))
+                    .push(", ")
+                    .push_bind(cursor.id)
+                    .push(")");
+            }
+            SortField::Id => {
+                query.push(" AND id").push(comparison).push_bind(cursor.id);
+            }
+        }
+    }
+
+    let direction = match order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+
+    match sort {
+        SortField::CreatedAt => {
+            query.push(format!(" ORDER BY created_at {direction}, id {direction}"));
+        }
+        SortField::Id => {
+            query.push(format!(" ORDER BY id {direction}"));
+        }
+    }
+
+    if let Some(limit) = params.limit {
+        query.push(" LIMIT ").push_bind(limit + 1);
+    }
+
+    let mut todos = query.build_query_as::<Todo>().fetch_all(pool).await?;
+
+    let next_cursor = match params.limit {
+        Some(limit) if todos.len() as i64 > limit => {
+            todos.truncate(limit as usize);
+            todos.last().map(|todo| {
+                let key = match sort {
+                    SortField::CreatedAt => todo.created_at.timestamp_micros(),
+                    SortField::Id => i64::from(todo.id),
+                };
+                Cursor { sort, order, key, id: todo.id }.encode()
+            })
+        }
+        _ => None,
+    };
+
+    Ok(Page { todos, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            sort: SortField::CreatedAt,
+            order: SortOrder::Desc,
+            key: 1_766_793_600_123_456,
+            id: 42,
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn cursor_must_match_requested_sort() {
+        let cursor = Cursor {
+            sort: SortField::Id,
+            order: SortOrder::Asc,
+            key: 7,
+            id: 7,
+        };
+        let params = ListParams {
+            cursor: Some(cursor.encode()),
+            ..Default::default()
+        };
+
+        assert_eq!(validate(&params), Err("Invalid cursor"));
+        assert_eq!(Cursor::decode("not-a-cursor"), None);
+    }
+
+    #[test]
+    fn as_of_stands_alone() {
+        let as_of = ListParams {
+            as_of: Some(Utc::now()),
+            ..Default::default()
+        };
+        assert_eq!(validate(&as_of), Ok(()));
+
+        let filtered = ListParams {
+            done: Some(true),
+            ..as_of
+        };
+        assert_eq!(validate(&filtered), Err("as_of cannot be combined with other parameters"));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("100%_done\\"), "100\\%\\_done\\\\");
+    }
+}
@@ -0,0 +1,275 @@
+//! Todo lists. Every todo belongs to exactly one; the default list is the
+//! one behind the original `/todos` collection routes, so clients that
+//! predate lists keep working.
+
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgConnection, PgExecutor, PgPool};
+use sqlx::FromRow;
+use todo_events::TodoAction;
+
+use crate::validation::normalize;
+use crate::{record_change, Actor, AppState, Error, Result, Todo, TODO_COLUMNS};
+
+/// The limit enforced by the `lists` table's check constraint.
+pub const MAX_NAME_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TodoList {
+    pub id: i32,
+    pub name: String,
+    pub is_default: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub todo_count: i64,
+    /// Todos in the list that are not done.
+    pub open_count: i64,
+}
+
+const LIST_COLUMNS: &str = "id, name, is_default, created_at, updated_at, \
+     (SELECT COUNT(*) FROM todos WHERE todos.list_id = lists.id AND todos.deleted_at IS NULL) AS todo_count, \
+     (SELECT COUNT(*) FROM todos \
+      WHERE todos.list_id = lists.id AND todos.deleted_at IS NULL AND NOT todos.done) AS open_count";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateList {
+    pub name: String,
+}
+
+/// `is_default` can only be set to `true`, which takes the flag from the
+/// current default list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchList {
+    pub name: Option<String>,
+    pub is_default: Option<bool>,
+}
+
+/// Normalizes a list name as todo content is normalized, returning the name
+/// to store.
+pub fn check_name(raw: &str) -> Result<String> {
+    if raw.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return Err(Error::Validation("List name must not contain control characters".to_string()));
+    }
+
+    let name = normalize(raw);
+    if name.is_empty() {
+        return Err(Error::Validation("List name cannot be empty".to_string()));
+    }
+    if name.chars().count() > MAX_NAME_CHARS {
+        return Err(Error::Validation(format!(
+            "List name must be {} characters or less",
+            MAX_NAME_CHARS
+        )));
+    }
+
+    Ok(name)
+}
+
+pub async fn default_list_id(pool: &PgPool) -> Result<i32, sqlx::Error> {
+    let (id,) = sqlx::query_as::<_, (i32,)>("SELECT id FROM lists WHERE is_default")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(id)
+}
+
+pub async fn list_exists(executor: impl PgExecutor<'_>, id: i32) -> Result<bool, sqlx::Error> {
+    let (exists,) = sqlx::query_as::<_, (bool,)>("SELECT EXISTS (SELECT 1 FROM lists WHERE id = $1)")
+        .bind(id)
+        .fetch_one(executor)
+        .await?;
+
+    Ok(exists)
+}
+
+/// Locks the list against deletion for the rest of the transaction, returning
+/// whether it exists.
+pub(crate) async fn lock_list_key(conn: &mut PgConnection, id: i32) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT 1 FROM lists WHERE id = $1 FOR KEY SHARE")
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+async fn fetch_list(pool: &PgPool, id: i32) -> Result<Option<TodoList>, sqlx::Error> {
+    sqlx::query_as::<_, TodoList>(&format!("SELECT {LIST_COLUMNS} FROM lists WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub(crate) async fn get_lists(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let lists = sqlx::query_as::<_, TodoList>(&format!(
+        "SELECT {LIST_COLUMNS} FROM lists ORDER BY is_default DESC, name, id"
+    ))
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(lists))
+}
+
+pub(crate) async fn create_list(
+    state: web::Data<AppState>,
+    new_list: web::Json<CreateList>,
+) -> Result<HttpResponse> {
+    let name = check_name(&new_list.name)?;
+
+    let list = sqlx::query_as::<_, TodoList>(&format!(
+        "INSERT INTO lists (name) VALUES ($1) RETURNING {LIST_COLUMNS}"
+    ))
+    .bind(&name)
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(|e| name_taken(e.into(), &name))?;
+
+    tracing::info!("Created list {}: {}", list.id, list.name);
+
+    Ok(list_response(HttpResponse::Created(), &list))
+}
+
+pub(crate) async fn get_list(state: web::Data<AppState>, id: web::Path<i32>) -> Result<HttpResponse> {
+    match fetch_list(&state.db_pool, id.into_inner()).await? {
+        Some(list) => Ok(list_response(HttpResponse::Ok(), &list)),
+        None => Err(Error::ListNotFound),
+    }
+}
+
+pub(crate) async fn patch_list(
+    state: web::Data<AppState>,
+    id: web::Path<i32>,
+    patch: web::Json<PatchList>,
+) -> Result<HttpResponse> {
+    let list_id = id.into_inner();
+
+    if patch.name.is_none() && patch.is_default.is_none() {
+        return Err(Error::Validation("Nothing to update: provide name and/or is_default".to_string()));
+    }
+    if patch.is_default == Some(false) {
+        return Err(Error::Validation(
+            "is_default can only be set to true; make another list the default instead".to_string(),
+        ));
+    }
+
+    let name = patch.name.as_deref().map(check_name).transpose()?;
+
+    let mut tx = state.db_pool.begin().await?;
+
+    if patch.is_default == Some(true) {
+        // Cleared first, as the unique index allows one default at a time.
+        sqlx::query("UPDATE lists SET is_default = FALSE WHERE is_default AND id <> $1")
+            .bind(list_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let list = sqlx::query_as::<_, TodoList>(&format!(
+        "UPDATE lists SET name = COALESCE($2, name), is_default = is_default OR $3 \
+         WHERE id = $1 RETURNING {LIST_COLUMNS}"
+    ))
+    .bind(list_id)
+    .bind(&name)
+    .bind(patch.is_default == Some(true))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| name_taken(e.into(), name.as_deref().unwrap_or_default()))?
+    .ok_or(Error::ListNotFound)?;
+
+    tx.commit().await?;
+
+    tracing::info!("Updated list {}", list.id);
+
+    Ok(list_response(HttpResponse::Ok(), &list))
+}
+
+/// Deletes the list, moving its todos to the trash of the default list, where
+/// they are restored to, and recording a `deleted` event for each todo that
+/// was not already in the trash. The default list cannot be deleted.
+pub(crate) async fn delete_list(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let list_id = id.into_inner();
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let (is_default,) = sqlx::query_as::<_, (bool,)>("SELECT is_default FROM lists WHERE id = $1 FOR UPDATE")
+        .bind(list_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::ListNotFound)?;
+
+    if is_default {
+        return Err(Error::Conflict("The default list cannot be deleted".to_string()));
+    }
+
+    let previous = sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos WHERE list_id = $1 AND deleted_at IS NULL ORDER BY id FOR UPDATE"
+    ))
+    .bind(list_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let todos = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos SET list_id = (SELECT id FROM lists WHERE is_default), \
+         deleted_at = COALESCE(deleted_at, NOW()) \
+         WHERE list_id = $1 RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(list_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for previous in &previous {
+        if let Some(todo) = todos.iter().find(|todo| todo.id == previous.id) {
+            record_change(&mut tx, &actor, TodoAction::Deleted, todo, Some(previous)).await?;
+        }
+    }
+
+    sqlx::query("DELETE FROM lists WHERE id = $1")
+        .bind(list_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    tracing::info!("Deleted list {} and moved its {} todos to the trash", list_id, previous.len());
+
+    if !previous.is_empty() {
+        state.outbox_wakeup.notify_one();
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Names the list behind a unique violation, the only conflict list writes
+/// can run into.
+fn name_taken(error: Error, name: &str) -> Error {
+    match error {
+        Error::Conflict(_) => Error::Conflict(format!("A list named \"{}\" already exists", name)),
+        other => other,
+    }
+}
+
+fn list_response(mut response: HttpResponseBuilder, list: &TodoList) -> HttpResponse {
+    response.content_type("application/json; charset=utf-8").json(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalized_and_limited() {
+        assert_eq!(check_name("  Groceries\t 🛒 ").unwrap(), "Groceries 🛒");
+        assert!(check_name(&"Ä".repeat(MAX_NAME_CHARS)).is_ok());
+        assert!(matches!(check_name(&"Ä".repeat(MAX_NAME_CHARS + 1)), Err(Error::Validation(_))));
+        assert!(matches!(check_name(" \n "), Err(Error::Validation(_))));
+        assert!(matches!(check_name("Work\u{7}"), Err(Error::Validation(_))));
+    }
+}
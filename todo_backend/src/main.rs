@@ -3,7 +3,7 @@ use std::net::TcpListener;
 use tracing_subscriber::fmt::time::UtcTime;
 use tracing_subscriber::EnvFilter;
 
-use todo_backend::{connect_to_database, connect_to_nats, run};
+use todo_backend::{connect_to_database, connect_to_nats, pending_migrations, run, run_migrations, Settings};
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
@@ -14,13 +14,31 @@ async fn main() -> Result<(), std::io::Error> {
         .with_env_filter(filter)
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let migrate_only = match args.first().map(String::as_str) {
+        Some("migrate") => true,
+        Some(other) => {
+            eprintln!("Unknown command: {}\nUsage: todo-backend [migrate [--dry-run]]", other);
+            std::process::exit(2);
+        }
+        None => false,
+    };
+
     let pool = connect_to_database()
         .await
         .expect("Failed to connect to database");
 
-    let nats_client = connect_to_nats()
+    if migrate_only {
+        let dry_run = args.iter().skip(1).any(|arg| arg == "--dry-run");
+        return migrate(&pool, dry_run).await;
+    }
+
+    run_migrations(&pool)
         .await
-        .expect("Failed to connect to NATS");
+        .expect("Failed to run database migrations");
+
+    let settings = Settings::from_env().expect("Invalid settings");
 
     let port = std::env::var("PORT")
         .unwrap_or_else(|_| "3000".to_string())
@@ -30,7 +48,49 @@ async fn main() -> Result<(), std::io::Error> {
     let address = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(&address)?;
 
+    let (nats_client, nats_status) = connect_to_nats()
+        .await
+        .expect("Invalid NATS configuration");
+
+    // As when connecting in the foreground, running out of attempts at the
+    // first connection stops the process.
+    tokio::spawn({
+        let nats_status = nats_status.clone();
+        async move {
+            nats_status.given_up().await;
+            tracing::error!("Failed to connect to NATS, giving up");
+            std::process::exit(1);
+        }
+    });
+
     tracing::info!("Todo backend server started on port {}", port);
 
-    run(listener, pool, nats_client)?.await
+    run(listener, pool, (nats_client, nats_status), settings)?.await
+}
+
+async fn migrate(pool: &sqlx::PgPool, dry_run: bool) -> Result<(), std::io::Error> {
+    if !dry_run {
+        run_migrations(pool)
+            .await
+            .expect("Failed to run database migrations");
+        return Ok(());
+    }
+
+    let pending = pending_migrations(pool)
+        .await
+        .expect("Failed to check database migrations");
+
+    if pending.is_empty() {
+        tracing::info!("Dry run: no pending migrations");
+    }
+
+    for migration in pending {
+        tracing::info!(
+            "Dry run: would apply migration {} ({})",
+            migration.version,
+            migration.description
+        );
+    }
+
+    Ok(())
 }
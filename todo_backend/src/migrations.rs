@@ -0,0 +1,57 @@
+use sqlx::migrate::{Migrate, MigrateError, Migration, Migrator};
+use sqlx::postgres::PgPool;
+
+/// Migrations from `./migrations`, embedded into the binary at compile time.
+/// Applied versions and their checksums are tracked in `_sqlx_migrations`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Applies every pending migration. Fails if an already applied migration
+/// has been modified since, so a drifted schema never gets served.
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrateError> {
+    tracing::info!("Running database migrations");
+
+    MIGRATOR.run(pool).await?;
+
+    tracing::info!("Database migrations are up to date");
+
+    Ok(())
+}
+
+/// Returns the migrations that `run_migrations` would apply, without touching
+/// the schema. Checksum drift is reported the same way as a real run.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<&'static Migration>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+
+    let (table_exists,) = sqlx::query_as::<_, (bool,)>(
+        "SELECT to_regclass('_sqlx_migrations') IS NOT NULL"
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !table_exists {
+        return Ok(MIGRATOR.iter().collect());
+    }
+
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version));
+    }
+
+    let applied = conn.list_applied_migrations().await?;
+
+    for applied_migration in &applied {
+        match MIGRATOR.iter().find(|m| m.version == applied_migration.version) {
+            Some(migration) if migration.checksum != applied_migration.checksum => {
+                return Err(MigrateError::VersionMismatch(migration.version));
+            }
+            Some(_) => {}
+            None => return Err(MigrateError::VersionMissing(applied_migration.version)),
+        }
+    }
+
+    let pending = MIGRATOR
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .collect();
+
+    Ok(pending)
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_nats::jetstream::{self, context::Publish};
+use sqlx::postgres::{PgConnection, PgPool};
+use sqlx::types::Json;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use todo_events::{TodoAction, TodoEvent};
+use uuid::Uuid;
+
+use crate::Todo;
+
+pub const EVENT_SOURCE: &str = "todo-backend";
+
+const BATCH_SIZE: i64 = 50;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY_SECS: i32 = 300;
+const DELIVERED_RETENTION: &str = "7 days";
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+/// Advisory lock key held by whichever replica is relaying.
+const RELAY_LOCK: i64 = 0x746f_646f_6f75_7462;
+
+/// Records a `todo.events` message in the outbox. Must be called on the same
+/// transaction as the mutation it describes, so both commit or neither does.
+pub async fn enqueue(
+    conn: &mut PgConnection,
+    action: TodoAction,
+    todo: &Todo,
+    previous: Option<&Todo>,
+) -> Result<Uuid, sqlx::Error> {
+    let event = TodoEvent::new(
+        EVENT_SOURCE,
+        action,
+        todo.snapshot(),
+        previous.map(Todo::snapshot),
+    );
+
+    sqlx::query("INSERT INTO todo_events_outbox (event_id, subject, payload) VALUES ($1, $2, $3)")
+        .bind(event.event_id)
+        .bind(todo_events::SUBJECT)
+        .bind(Json(&event))
+        .execute(conn)
+        .await?;
+
+    Ok(event.event_id)
+}
+
+/// Publishes pending outbox rows into the `todo.events` JetStream stream
+/// until the task is dropped. Rows are marked delivered only after the stream
+/// has acknowledged them, so a crash in between causes a republish with the
+/// same `Nats-Msg-Id`, which the stream discards as a duplicate.
+pub async fn relay(pool: PgPool, nats_client: async_nats::Client, wakeup: Arc<Notify>) {
+    tracing::info!("Outbox relay started");
+
+    let jetstream = jetstream::new(nats_client);
+    let mut stream_ready = false;
+    let mut last_cleanup = Instant::now();
+
+    loop {
+        if !stream_ready {
+            match todo_events::jetstream::get_or_create_stream(&jetstream).await {
+                Ok(_) => {
+                    tracing::info!("JetStream stream {} is ready", todo_events::STREAM);
+                    stream_ready = true;
+                }
+                Err(e) => tracing::error!("Failed to set up JetStream stream {}: {}", todo_events::STREAM, e),
+            }
+        }
+
+        let relayed = if stream_ready {
+            relay_batch(&pool, &jetstream).await
+        } else {
+            Ok(0)
+        };
+
+        match relayed {
+            Ok(published) if published as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(e) => tracing::error!("Outbox relay failed: {}", e),
+        }
+
+        if last_cleanup.elapsed() >= CLEANUP_INTERVAL {
+            if let Err(e) = delete_delivered(&pool).await {
+                tracing::warn!("Failed to clean up delivered outbox rows: {}", e);
+            }
+            last_cleanup = Instant::now();
+        }
+
+        tokio::select! {
+            _ = wakeup.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct OutboxRow {
+    id: i64,
+    event_id: Uuid,
+    subject: String,
+    payload: Json<serde_json::Value>,
+    attempts: i32,
+}
+
+/// Publishes up to one batch of due rows in id order, stopping at the first
+/// failure since the rest of the batch would most likely fail the same way.
+///
+/// Only one relay, across all replicas, works at a time: the others skip the
+/// round while [`RELAY_LOCK`] is held. Rows are read in a single statement and
+/// published outside any transaction, so a slow stream holds no row locks.
+async fn relay_batch(pool: &PgPool, jetstream: &jetstream::Context) -> Result<usize, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(RELAY_LOCK)
+        .fetch_one(&mut *conn)
+        .await?;
+    if !locked {
+        return Ok(0);
+    }
+
+    let relayed = relay_locked(&mut conn, jetstream).await;
+
+    let unlocked = sqlx::query_scalar::<_, bool>("SELECT pg_advisory_unlock($1)")
+        .bind(RELAY_LOCK)
+        .fetch_one(&mut *conn)
+        .await;
+    if !matches!(unlocked, Ok(true)) {
+        // Closing the session is the only other way to free the lock.
+        tracing::warn!("Failed to release the outbox relay lock, closing its connection");
+        conn.close_on_drop();
+    }
+
+    relayed
+}
+
+/// The body of [`relay_batch`], run while holding [`RELAY_LOCK`].
+///
+/// Events about one todo are published in the order they were recorded: a
+/// row is skipped while an older row for the same todo is undelivered and
+/// not yet due, e.g. waiting to be retried.
+async fn relay_locked(conn: &mut PgConnection, jetstream: &jetstream::Context) -> Result<usize, sqlx::Error> {
+    let rows = sqlx::query_as::<_, OutboxRow>(
+        "SELECT id, event_id, subject, payload, attempts FROM todo_events_outbox AS pending \
+         WHERE delivered_at IS NULL AND next_attempt_at <= NOW() \
+           AND NOT EXISTS ( \
+               SELECT 1 FROM todo_events_outbox AS older \
+               WHERE older.delivered_at IS NULL AND older.next_attempt_at > NOW() \
+                 AND older.payload->'todo'->'id' = pending.payload->'todo'->'id' AND older.id < pending.id) \
+         ORDER BY id LIMIT $1"
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut published = Vec::with_capacity(rows.len());
+    let mut failure = None;
+
+    for row in &rows {
+        match publish(jetstream, row).await {
+            Ok(duplicate) => {
+                if duplicate {
+                    tracing::debug!("Outbox event {} was already in the stream", row.event_id);
+                }
+                published.push(row.id);
+            }
+            Err(e) => {
+                failure = Some((row, e));
+                break;
+            }
+        }
+    }
+
+    if !published.is_empty() {
+        sqlx::query("UPDATE todo_events_outbox SET delivered_at = NOW() WHERE id = ANY($1)")
+            .bind(&published)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    if let Some((row, error)) = failure {
+        let delay_secs = 2_i32.saturating_pow(row.attempts as u32).min(MAX_RETRY_DELAY_SECS);
+
+        tracing::warn!(
+            "Failed to publish outbox event {} (attempt {}), retrying in {}s: {}",
+            row.event_id,
+            row.attempts + 1,
+            delay_secs,
+            error
+        );
+
+        sqlx::query(
+            "UPDATE todo_events_outbox SET attempts = attempts + 1, last_error = $2, \
+             next_attempt_at = clock_timestamp() + make_interval(secs => $3) WHERE id = $1"
+        )
+        .bind(row.id)
+        .bind(error)
+        .bind(f64::from(delay_secs))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    if !published.is_empty() {
+        tracing::debug!("Published {} outbox events", published.len());
+    }
+
+    Ok(published.len())
+}
+
+/// Publishes one row and waits for the stream's acknowledgement, returning
+/// whether the stream already had a message with this event id.
+async fn publish(jetstream: &jetstream::Context, row: &OutboxRow) -> Result<bool, String> {
+    let message = Publish::build()
+        .payload(row.payload.0.to_string().into())
+        .message_id(row.event_id.to_string());
+
+    let ack = jetstream
+        .send_publish(row.subject.clone(), message)
+        .await
+        .map_err(|e| e.to_string())?
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(ack.duplicate)
+}
+
+async fn delete_delivered(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM todo_events_outbox WHERE delivered_at < NOW() - $1::INTERVAL"
+    )
+    .bind(DELIVERED_RETENTION)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        tracing::info!("Removed {} delivered outbox rows", result.rows_affected());
+    }
+
+    Ok(())
+}
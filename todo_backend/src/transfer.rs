@@ -0,0 +1,960 @@
+//! Exporting todos to, and importing them from, JSON, CSV and todo.txt, to
+//! move todos between environments.
+//!
+//! Exports carry what a todo means rather than how it is stored: no ids or
+//! versions, and the list by name. todo.txt has no room for lists, so
+//! todo.txt imports go to the requested or default list; tags containing
+//! spaces are written with underscores instead.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use actix_web::http::header;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgConnection, Postgres};
+use sqlx::{Connection, Transaction};
+use todo_events::{Priority, TodoAction};
+
+use crate::{
+    apply_todo_patch, check_new_todo, lists, record_change, set_tags, Actor, AppState, ContentPolicy, CreateTodo, Error,
+    PatchTodo, Result, Todo, TODO_COLUMNS,
+};
+
+/// Request bodies larger than this are rejected before being parsed.
+pub const MAX_IMPORT_BYTES: usize = 8 * 1024 * 1024;
+pub const MAX_IMPORT_RECORDS: usize = 10_000;
+
+const EXPORT_PAGE_SIZE: i64 = 500;
+const CSV_HEADER: [&str; 8] = ["content", "done", "priority", "due_at", "tags", "list", "created_at", "completed_at"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// An array of [`TodoRecord`]s.
+    #[default]
+    Json,
+    /// A header line followed by one [`TodoRecord`] per line, tags separated
+    /// by commas.
+    Csv,
+    /// One todo per line in the todo.txt format.
+    #[serde(rename = "todotxt")]
+    TodoTxt,
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json; charset=utf-8",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::TodoTxt => "text/plain; charset=utf-8",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Json => "todos.json",
+            Self::Csv => "todos.csv",
+            Self::TodoTxt => "todo.txt",
+        }
+    }
+}
+
+/// What an import does with a todo whose content matches, ignoring case, a
+/// todo in the same list, including one imported earlier in the same file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Duplicates {
+    /// Leaves the existing todo alone.
+    #[default]
+    Skip,
+    /// Overwrites the existing todo's done, priority, due date and tags.
+    Update,
+    /// Imports the todo anyway.
+    Allow,
+}
+
+/// A todo as exported, and as accepted by imports, where only `content` is
+/// required.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TodoRecord {
+    pub content: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub list: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl TodoRecord {
+    fn new(todo: &Todo, list: Option<&str>) -> Self {
+        Self {
+            content: todo.content.clone(),
+            done: todo.done,
+            priority: todo.priority,
+            due_at: todo.due_at,
+            tags: todo.tags.clone(),
+            list: list.map(str::to_string),
+            created_at: Some(todo.created_at),
+            completed_at: todo.completed_at,
+        }
+    }
+}
+
+/// A [`TodoRecord`] as a CSV row, in the order of [`CSV_HEADER`]. Empty cells
+/// stand for missing values.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRecord {
+    content: String,
+    done: Option<bool>,
+    priority: Option<Priority>,
+    due_at: Option<DateTime<Utc>>,
+    tags: Option<String>,
+    list: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+impl From<TodoRecord> for CsvRecord {
+    fn from(record: TodoRecord) -> Self {
+        Self {
+            content: record.content,
+            done: Some(record.done),
+            priority: Some(record.priority),
+            due_at: record.due_at,
+            // Tags cannot contain commas.
+            tags: Some(record.tags.join(",")),
+            list: record.list,
+            created_at: record.created_at,
+            completed_at: record.completed_at,
+        }
+    }
+}
+
+impl From<CsvRecord> for TodoRecord {
+    fn from(record: CsvRecord) -> Self {
+        Self {
+            content: record.content,
+            done: record.done.unwrap_or_default(),
+            priority: record.priority.unwrap_or_default(),
+            due_at: record.due_at,
+            tags: record
+                .tags
+                .iter()
+                .flat_map(|tags| tags.split(','))
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            list: record.list.filter(|list| !list.is_empty()),
+            created_at: record.created_at,
+            completed_at: record.completed_at,
+        }
+    }
+}
+
+/// Writes a todo as a todo.txt line: `x`, the completion and creation dates,
+/// the priority as `(A)` for high and `(C)` for low (`pri:` once done), the
+/// content, then `+tags` and `due:`.
+pub fn to_todotxt(record: &TodoRecord) -> String {
+    let mut parts = Vec::new();
+
+    if record.done {
+        parts.push("x".to_string());
+        if let Some(completed_at) = record.completed_at {
+            parts.push(completed_at.date_naive().to_string());
+        }
+    } else if let Some(letter) = priority_letter(record.priority) {
+        parts.push(format!("({})", letter));
+    }
+    // A lone date after `x` would be read as the completion date.
+    if let Some(created_at) = record.created_at.filter(|_| !record.done || record.completed_at.is_some()) {
+        parts.push(created_at.date_naive().to_string());
+    }
+
+    parts.push(record.content.clone());
+    parts.extend(record.tags.iter().map(|tag| format!("+{}", tag.replace(' ', "_"))));
+
+    if let Some(due_at) = record.due_at {
+        parts.push(format!("due:{}", todotxt_time(due_at)));
+    }
+    if record.done {
+        if let Some(letter) = priority_letter(record.priority) {
+            parts.push(format!("pri:{}", letter));
+        }
+    }
+
+    parts.join(" ")
+}
+
+/// Reads a todo.txt line. `+tags`, `due:` and `pri:` are only taken from the
+/// end of the line, so a `+word` inside the text stays part of the content.
+pub fn parse_todotxt(line: &str) -> Result<TodoRecord, String> {
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    tokens.reverse();
+
+    let mut record = TodoRecord {
+        content: String::new(),
+        done: false,
+        priority: Priority::Normal,
+        due_at: None,
+        tags: Vec::new(),
+        list: None,
+        created_at: None,
+        completed_at: None,
+    };
+
+    if tokens.last() == Some(&"x") {
+        tokens.pop();
+        record.done = true;
+        record.completed_at = tokens.last().and_then(|token| todotxt_date(token));
+        if record.completed_at.is_some() {
+            tokens.pop();
+        }
+    } else if let Some(priority) = tokens.last().and_then(|token| parse_priority_letter(token, true)) {
+        tokens.pop();
+        record.priority = priority;
+    }
+    record.created_at = tokens.last().and_then(|token| todotxt_date(token));
+    if record.created_at.is_some() {
+        tokens.pop();
+    }
+
+    tokens.reverse();
+    while let Some(token) = tokens.last().copied() {
+        if let Some(tag) = token.strip_prefix('+').filter(|tag| !tag.is_empty()) {
+            record.tags.insert(0, tag.to_string());
+        } else if let Some(due) = token.strip_prefix("due:") {
+            record.due_at = Some(parse_todotxt_time(due).ok_or_else(|| format!("invalid due date '{}'", due))?);
+        } else if let Some(letter) = token.strip_prefix("pri:") {
+            record.priority =
+                parse_priority_letter(letter, false).ok_or_else(|| format!("invalid priority '{}'", letter))?;
+        } else {
+            break;
+        }
+        tokens.pop();
+    }
+
+    record.content = tokens.join(" ");
+    if record.content.is_empty() {
+        return Err("line has no text".to_string());
+    }
+
+    Ok(record)
+}
+
+fn priority_letter(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::High => Some('A'),
+        Priority::Normal => None,
+        Priority::Low => Some('C'),
+    }
+}
+
+/// `A` is high, `B` normal and anything lower low; `parenthesized` reads the
+/// `(A)` form.
+fn parse_priority_letter(token: &str, parenthesized: bool) -> Option<Priority> {
+    let letter = if parenthesized {
+        token.strip_prefix('(')?.strip_suffix(')')?
+    } else {
+        token
+    };
+
+    match letter.as_bytes() {
+        [b'A'] => Some(Priority::High),
+        [b'B'] => Some(Priority::Normal),
+        [b'C'..=b'Z'] => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+fn todotxt_date(token: &str) -> Option<DateTime<Utc>> {
+    NaiveDate::parse_from_str(token, "%Y-%m-%d")
+        .ok()
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+}
+
+/// A date for midnight UTC, the full time otherwise.
+fn todotxt_time(time: DateTime<Utc>) -> String {
+    if time.time() == NaiveTime::MIN {
+        time.date_naive().to_string()
+    } else {
+        time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+    }
+}
+
+fn parse_todotxt_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| todotxt_date(value))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: Format,
+    /// Only export this list; every list otherwise.
+    pub list_id: Option<i32>,
+}
+
+/// Streams every todo that is not in the trash, oldest first, fetching them a
+/// page at a time. Every page is read from the same snapshot, so todos moved
+/// or changed while the export runs are neither missed nor exported twice.
+pub(crate) async fn export_todos(
+    state: web::Data<AppState>,
+    params: web::Query<ExportParams>,
+) -> Result<HttpResponse> {
+    let ExportParams { format, list_id } = params.into_inner();
+
+    // Held until the body has been streamed; being read-only, it is simply
+    // rolled back when dropped.
+    let mut tx = state.db_pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    if let Some(list_id) = list_id {
+        if !lists::list_exists(&mut *tx, list_id).await? {
+            return Err(Error::ListNotFound);
+        }
+    }
+    let list_names: HashMap<i32, String> = sqlx::query_as::<_, (i32, String)>("SELECT id, name FROM lists")
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    tracing::info!("Exporting todos as {:?}", format);
+
+    let export = Export {
+        tx,
+        format,
+        list_id,
+        list_names,
+        after: None,
+        done: false,
+    };
+    let body = stream::try_unfold(export, |mut export| async move {
+        Ok::<_, Error>(export.next_chunk().await?.map(|chunk| (chunk, export)))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        ))
+        .streaming(body))
+}
+
+struct Export {
+    tx: Transaction<'static, Postgres>,
+    format: Format,
+    list_id: Option<i32>,
+    list_names: HashMap<i32, String>,
+    /// The id of the last exported todo, `None` before the first page.
+    after: Option<i32>,
+    done: bool,
+}
+
+impl Export {
+    /// The next piece of the body: the opening (the JSON bracket or CSV
+    /// header), one per page of todos, then the closing.
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut chunk = Vec::new();
+        if self.after.is_none() {
+            match self.format {
+                Format::Json => chunk.push(b'['),
+                Format::Csv => chunk.extend(csv_line(&CSV_HEADER)),
+                Format::TodoTxt => {}
+            }
+        }
+
+        let todos = sqlx::query_as::<_, Todo>(&format!(
+            "SELECT {TODO_COLUMNS} FROM todos \
+             WHERE deleted_at IS NULL AND ($1::INTEGER IS NULL OR list_id = $1) AND id > $2 \
+             ORDER BY id LIMIT $3"
+        ))
+        .bind(self.list_id)
+        .bind(self.after.unwrap_or(0))
+        .bind(EXPORT_PAGE_SIZE)
+        .fetch_all(&mut *self.tx)
+        .await?;
+
+        for (index, todo) in todos.iter().enumerate() {
+            let record = TodoRecord::new(todo, self.list_names.get(&todo.list_id).map(String::as_str));
+
+            match self.format {
+                Format::Json => {
+                    if self.after.is_some() || index > 0 {
+                        chunk.push(b',');
+                    }
+                    serde_json::to_writer(&mut chunk, &record)?;
+                }
+                Format::Csv => chunk.extend(csv_line(&CsvRecord::from(record))),
+                Format::TodoTxt => {
+                    chunk.extend(to_todotxt(&record).as_bytes());
+                    chunk.push(b'\n');
+                }
+            }
+        }
+
+        if (todos.len() as i64) < EXPORT_PAGE_SIZE {
+            self.done = true;
+            if self.format == Format::Json {
+                chunk.push(b']');
+            }
+        }
+        // A first page that is also the last still has to move past `None`.
+        self.after = Some(todos.last().map_or(self.after.unwrap_or(0), |todo| todo.id));
+
+        Ok(Some(Bytes::from(chunk)))
+    }
+}
+
+fn csv_line(record: &impl Serialize) -> Vec<u8> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    // Writing flat records to memory cannot fail.
+    writer.serialize(record).expect("CSV record should serialize");
+    writer.into_inner().expect("CSV writer should flush to memory")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub format: Format,
+    /// Imports every todo into this list, whatever list the file names.
+    pub list_id: Option<i32>,
+    #[serde(default)]
+    pub duplicates: Duplicates,
+    /// Validates and reports without saving anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineStatus {
+    Created,
+    Updated,
+    Skipped,
+    Invalid,
+}
+
+/// The outcome of one todo of an import. `line` is the line number for CSV
+/// and todo.txt, and the position in the array for JSON.
+#[derive(Debug, Serialize)]
+pub struct LineReport {
+    pub line: usize,
+    pub status: LineStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub format: Format,
+    pub duplicates: Duplicates,
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub invalid: usize,
+    pub lines: Vec<LineReport>,
+}
+
+/// Parses the body, returning each todo, or why it could not be read, with
+/// its line.
+pub fn parse(format: Format, body: &str) -> Result<Vec<(usize, Result<TodoRecord, String>)>> {
+    match format {
+        Format::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_str(body)
+                .map_err(|e| Error::Validation(format!("Expected a JSON array of todos: {}", e)))?;
+
+            Ok(values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| (index + 1, serde_json::from_value(value).map_err(|e| e.to_string())))
+                .collect())
+        }
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body.as_bytes());
+            let headers = reader
+                .headers()
+                .map_err(|e| Error::Validation(format!("Could not read the CSV header: {}", e)))?
+                .clone();
+            if !headers.iter().any(|name| name == "content") {
+                return Err(Error::Validation("The CSV header has no content column".to_string()));
+            }
+
+            Ok(reader
+                .records()
+                .map(|row| {
+                    let position = match &row {
+                        Ok(row) => row.position(),
+                        Err(e) => e.position(),
+                    };
+                    let line = position.map_or(0, |position| position.line() as usize);
+                    let record = row
+                        .and_then(|row| row.deserialize::<CsvRecord>(Some(&headers)))
+                        .map(TodoRecord::from)
+                        .map_err(|e| e.to_string());
+                    (line, record)
+                })
+                .collect())
+        }
+        Format::TodoTxt => Ok(body
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, parse_todotxt(line)))
+            .collect()),
+    }
+}
+
+pub(crate) async fn import_todos(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    params: web::Query<ImportParams>,
+    body: Bytes,
+) -> Result<HttpResponse> {
+    let actor = Actor::from_request(&req, &state.actor_header)?;
+    let ImportParams {
+        format,
+        list_id,
+        duplicates,
+        dry_run,
+    } = params.into_inner();
+
+    let body = std::str::from_utf8(&body).map_err(|_| Error::Validation("The import must be UTF-8".to_string()))?;
+    let records = parse(format, body)?;
+    if records.len() > MAX_IMPORT_RECORDS {
+        return Err(Error::Validation(format!(
+            "An import can hold at most {} todos",
+            MAX_IMPORT_RECORDS
+        )));
+    }
+
+    tracing::info!("Importing {} todos from {:?} (dry run: {})", records.len(), format, dry_run);
+
+    let mut tx = state.db_pool.begin().await?;
+
+    if let Some(list_id) = list_id {
+        if !lists::lock_list_key(&mut tx, list_id).await? {
+            return Err(Error::ListNotFound);
+        }
+    }
+
+    let mut import = Import {
+        actor,
+        duplicates,
+        list_id,
+        list_ids: HashMap::new(),
+        existing: HashMap::new(),
+    };
+    let mut lines = Vec::with_capacity(records.len());
+
+    for (line, record) in records {
+        let report = match record {
+            Ok(record) => {
+                let mut savepoint = tx.begin().await?;
+                match import.apply(&mut savepoint, &state.content_policy, record).await {
+                    Ok((status, detail, todo)) => {
+                        savepoint.commit().await?;
+                        LineReport { line, status, detail, todo }
+                    }
+                    Err(e) if e.is_internal() => return Err(e),
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        // Lists and todos seen in the failed savepoint are gone.
+                        import.list_ids.clear();
+                        import.existing.clear();
+                        LineReport {
+                            line,
+                            status: LineStatus::Invalid,
+                            detail: Some(e.to_string()),
+                            todo: None,
+                        }
+                    }
+                }
+            }
+            Err(detail) => LineReport {
+                line,
+                status: LineStatus::Invalid,
+                detail: Some(detail),
+                todo: None,
+            },
+        };
+        lines.push(report);
+    }
+
+    let count = |status| lines.iter().filter(|line| line.status == status).count();
+    let report = ImportReport {
+        format,
+        duplicates,
+        dry_run,
+        created: count(LineStatus::Created),
+        updated: count(LineStatus::Updated),
+        skipped: count(LineStatus::Skipped),
+        invalid: count(LineStatus::Invalid),
+        lines,
+    };
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+
+        if report.created + report.updated > 0 {
+            state.outbox_wakeup.notify_one();
+        }
+    }
+
+    tracing::info!(
+        "Import done: {} created, {} updated, {} skipped, {} invalid",
+        report.created,
+        report.updated,
+        report.skipped,
+        report.invalid
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(report))
+}
+
+struct Import {
+    actor: Actor,
+    duplicates: Duplicates,
+    list_id: Option<i32>,
+    /// Lists by name, as found or created.
+    list_ids: HashMap<String, i32>,
+    /// The todos of each list seen so far by lowercased content.
+    existing: HashMap<i32, HashMap<String, Todo>>,
+}
+
+impl Import {
+    async fn apply(
+        &mut self,
+        conn: &mut PgConnection,
+        policy: &ContentPolicy,
+        record: TodoRecord,
+    ) -> Result<(LineStatus, Option<String>, Option<Todo>)> {
+        let new_todo = check_new_todo(
+            policy,
+            CreateTodo {
+                content: record.content.clone(),
+                priority: record.priority,
+                due_at: record.due_at,
+                tags: record.tags.clone(),
+            },
+        )?;
+
+        let list_id = match (self.list_id, &record.list) {
+            (Some(list_id), _) => list_id,
+            (None, Some(name)) => self.list_named(conn, name).await?,
+            (None, None) => {
+                sqlx::query_as::<_, (i32,)>("SELECT id FROM lists WHERE is_default")
+                    .fetch_one(&mut *conn)
+                    .await?
+                    .0
+            }
+        };
+
+        if let Entry::Vacant(entry) = self.existing.entry(list_id) {
+            let todos = sqlx::query_as::<_, Todo>(&format!(
+                "SELECT {TODO_COLUMNS} FROM todos WHERE list_id = $1 AND deleted_at IS NULL ORDER BY id"
+            ))
+            .bind(list_id)
+            .fetch_all(&mut *conn)
+            .await?;
+
+            let todos = todos.into_iter().rev().map(|todo| (todo.content.to_lowercase(), todo));
+            entry.insert(todos.collect());
+        }
+        let key = new_todo.content.to_lowercase();
+        let existing = self.existing[&list_id].get(&key).cloned();
+
+        let todo = match (existing, self.duplicates) {
+            (Some(existing), Duplicates::Skip) => {
+                return Ok((LineStatus::Skipped, Some(format!("Duplicate of todo {}", existing.id)), None));
+            }
+            (Some(existing), Duplicates::Update) => {
+                if (existing.done, existing.priority, existing.due_at, &existing.tags)
+                    == (record.done, new_todo.priority, new_todo.due_at, &new_todo.tags)
+                {
+                    return Ok((LineStatus::Skipped, Some(format!("Todo {} is unchanged", existing.id)), None));
+                }
+
+                let patch = PatchTodo {
+                    done: Some(record.done),
+                    priority: Some(new_todo.priority),
+                    due_at: Some(new_todo.due_at),
+                    tags: Some(new_todo.tags),
+                    ..PatchTodo::default()
+                };
+                let todo = apply_todo_patch(conn, &self.actor, existing.id, &patch, Some(&[existing.version]))
+                    .await?
+                    .ok_or_else(|| Error::Conflict(format!("Todo {} changed during the import", existing.id)))?;
+                self.existing.get_mut(&list_id).unwrap().insert(key, todo.clone());
+
+                return Ok((LineStatus::Updated, None, Some(todo)));
+            }
+            _ => insert(conn, &self.actor, list_id, &record, &new_todo).await?,
+        };
+
+        self.existing.get_mut(&list_id).unwrap().entry(key).or_insert(todo.clone());
+
+        Ok((LineStatus::Created, None, Some(todo)))
+    }
+
+    /// The list with the given name, created if there is none.
+    async fn list_named(&mut self, conn: &mut PgConnection, name: &str) -> Result<i32> {
+        let name = lists::check_name(name)?;
+        if let Some(id) = self.list_ids.get(&name) {
+            return Ok(*id);
+        }
+
+        sqlx::query("INSERT INTO lists (name) VALUES ($1) ON CONFLICT (name) DO NOTHING")
+            .bind(&name)
+            .execute(&mut *conn)
+            .await?;
+        let (id,) = sqlx::query_as::<_, (i32,)>("SELECT id FROM lists WHERE name = $1 FOR KEY SHARE")
+            .bind(&name)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        self.list_ids.insert(name, id);
+
+        Ok(id)
+    }
+}
+
+/// Inserts an imported todo, keeping its creation and completion times.
+async fn insert(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    list_id: i32,
+    record: &TodoRecord,
+    new_todo: &CreateTodo,
+) -> Result<Todo> {
+    let mut todo = sqlx::query_as::<_, Todo>(&format!(
+        "INSERT INTO todos (content, priority, due_at, list_id, done, created_at, updated_at, completed_at) \
+         VALUES ($1, $2, $3, $4, $5, COALESCE($6, NOW()), NOW(), CASE WHEN $5 THEN COALESCE($7, NOW()) END) \
+         RETURNING {TODO_COLUMNS}"
+    ))
+    .bind(&new_todo.content)
+    .bind(new_todo.priority.as_str())
+    .bind(new_todo.due_at)
+    .bind(list_id)
+    .bind(record.done)
+    .bind(record.created_at)
+    .bind(record.completed_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    set_tags(conn, todo.id, &new_todo.tags).await?;
+    todo.tags = new_todo.tags.clone();
+
+    record_change(conn, actor, TodoAction::Created, &todo, None).await?;
+
+    Ok(todo)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+    use sqlx::postgres::PgPool;
+
+    use super::*;
+
+    async fn app(
+        pool: PgPool,
+    ) -> impl Service<actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error> {
+        test::init_service(
+            App::new()
+                .app_data(AppState::for_tests(pool).await)
+                .route("/todos/import", web::post().to(import_todos)),
+        )
+        .await
+    }
+
+    async fn import(
+        app: &impl Service<actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error>,
+        query: &str,
+        todos: serde_json::Value,
+    ) -> serde_json::Value {
+        let req = TestRequest::post().uri(&format!("/todos/import?{}", query)).set_json(todos);
+        test::call_and_read_body_json(app, req.to_request()).await
+    }
+
+    async fn stored(pool: &PgPool) -> Vec<(String, bool)> {
+        sqlx::query_as("SELECT content, done FROM todos ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn record(content: &str) -> TodoRecord {
+        TodoRecord {
+            content: content.to_string(),
+            done: false,
+            priority: Priority::Normal,
+            due_at: None,
+            tags: Vec::new(),
+            list: None,
+            created_at: None,
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn todotxt_lines_round_trip() {
+        let day = |day| todotxt_date(&format!("2026-10-{:02}", day));
+
+        let records = [
+            TodoRecord {
+                priority: Priority::High,
+                due_at: "2026-10-20T09:30:00Z".parse().ok(),
+                tags: vec!["errands".to_string(), "home office".to_string()],
+                created_at: day(1),
+                ..record("Call +bob about the (A) plan")
+            },
+            TodoRecord {
+                done: true,
+                priority: Priority::Low,
+                created_at: day(1),
+                completed_at: day(18),
+                ..record("Read the manual")
+            },
+            TodoRecord {
+                done: true,
+                created_at: day(2),
+                due_at: day(3),
+                ..record("Pay rent")
+            },
+        ];
+        let lines: Vec<String> = records.iter().map(to_todotxt).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "(A) 2026-10-01 Call +bob about the (A) plan +errands +home_office due:2026-10-20T09:30:00Z",
+                "x 2026-10-18 2026-10-01 Read the manual pri:C",
+                "x Pay rent due:2026-10-03",
+            ]
+        );
+
+        let parsed: Vec<TodoRecord> = lines.iter().map(|line| parse_todotxt(line).unwrap()).collect();
+        assert_eq!(parsed[0].tags, ["errands", "home_office"]);
+        assert_eq!(parsed[0].content, records[0].content);
+        assert_eq!(parsed[1], records[1]);
+        assert_eq!(parsed[2].created_at, None);
+
+        assert_eq!(parse_todotxt("(B) Plain").unwrap(), record("Plain"));
+        assert!(parse_todotxt("x 2026-10-18 +tag").is_err());
+        assert!(parse_todotxt("Water plants due:soon").is_err());
+    }
+
+    #[test]
+    fn csv_rows_report_their_line() {
+        let body = "content,done,tags,due_at\n\
+                    Buy milk,true,\"errands,home\",\n\
+                    \"Two\nlines\",false,,\n\
+                    Broken,maybe,,\n";
+
+        let records = parse(Format::Csv, body).unwrap();
+        let lines: Vec<usize> = records.iter().map(|(line, _)| *line).collect();
+
+        assert_eq!(lines, [2, 3, 5]);
+        assert_eq!(
+            records[0].1.as_ref().unwrap(),
+            &TodoRecord {
+                done: true,
+                tags: vec!["errands".to_string(), "home".to_string()],
+                ..record("Buy milk")
+            }
+        );
+        assert!(records[2].1.is_err());
+        assert!(parse(Format::Csv, "title\nBuy milk\n").is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn duplicates_are_skipped_updated_or_allowed(pool: PgPool) {
+        let app = app(pool.clone()).await;
+        let duplicate = serde_json::json!([{ "content": "buy MILK", "done": true }]);
+
+        let report = import(&app, "", serde_json::json!([{ "content": "Buy milk" }])).await;
+        assert_eq!(report["created"], 1);
+
+        let report = import(&app, "duplicates=skip", duplicate.clone()).await;
+        assert_eq!(report["skipped"], 1);
+        assert_eq!(report["created"], 0);
+        assert_eq!(stored(&pool).await, [("Buy milk".to_string(), false)]);
+
+        let report = import(&app, "duplicates=update", duplicate.clone()).await;
+        assert_eq!(report["updated"], 1);
+        assert_eq!(stored(&pool).await, [("Buy milk".to_string(), true)]);
+
+        let report = import(&app, "duplicates=update", duplicate.clone()).await;
+        assert_eq!(report["lines"][0]["status"], "skipped");
+
+        let report = import(&app, "duplicates=allow", duplicate).await;
+        assert_eq!(report["created"], 1);
+        assert_eq!(
+            stored(&pool).await,
+            [("Buy milk".to_string(), true), ("buy MILK".to_string(), true)]
+        );
+    }
+
+    #[sqlx::test(migrator = "crate::migrations::MIGRATOR")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn dry_runs_report_without_saving(pool: PgPool) {
+        let app = app(pool.clone()).await;
+
+        let report = import(
+            &app,
+            "dry_run=true",
+            serde_json::json!([
+                { "content": "Plan the week", "list": "Work" },
+                { "content": "Plan the week", "list": "Work" },
+                { "content": "Oops", "priority": "urgent" },
+            ]),
+        )
+        .await;
+
+        assert_eq!(report["dry_run"], true);
+        assert_eq!(report["created"], 1);
+        assert_eq!(report["skipped"], 1);
+        assert_eq!(report["invalid"], 1);
+        assert!(stored(&pool).await.is_empty());
+
+        let lists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM lists WHERE name = 'Work'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(lists, 0);
+    }
+}
@@ -0,0 +1,103 @@
+//! Deleted todos are moved to the trash, from where they can be restored
+//! until the purge task removes them for good once the retention has passed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::postgres::PgPool;
+use tokio::sync::Notify;
+use todo_events::TodoAction;
+
+use crate::{record_change, Actor, Todo, TODO_COLUMNS};
+
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+const PURGE_BATCH_SIZE: i64 = 100;
+
+/// Reads `TODO_TRASH_RETENTION_DAYS`, falling back to 30 days.
+pub fn retention_from_env() -> Result<Duration, String> {
+    match std::env::var("TODO_TRASH_RETENTION_DAYS") {
+        Ok(days) => days
+            .parse::<u64>()
+            .ok()
+            .filter(|days| *days > 0)
+            .and_then(|days| days.checked_mul(24 * 3600))
+            .map(Duration::from_secs)
+            .ok_or_else(|| "TODO_TRASH_RETENTION_DAYS must be a positive integer".to_string()),
+        Err(_) => Ok(DEFAULT_RETENTION),
+    }
+}
+
+/// Every todo in the trash, most recently deleted first.
+pub async fn fetch_trash(pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
+    sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC"
+    ))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn fetch_trashed(pool: &PgPool, id: i32) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos WHERE id = $1 AND deleted_at IS NOT NULL"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Purges expired todos from the trash every hour until the task is dropped.
+pub async fn purge(pool: PgPool, retention: Duration, outbox_wakeup: Arc<Notify>) {
+    tracing::info!("Trash purge started, retention is {} days", retention.as_secs() / (24 * 3600));
+
+    loop {
+        match purge_expired(&pool, retention).await {
+            Ok(0) => {}
+            Ok(purged) => {
+                tracing::info!("Purged {} todos from the trash", purged);
+                outbox_wakeup.notify_one();
+            }
+            Err(e) => tracing::error!("Failed to purge the trash: {}", e),
+        }
+
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
+
+/// Deletes the todos trashed longer than `retention` ago in batches,
+/// recording a `purged` event for each. Returns how many were deleted.
+pub async fn purge_expired(pool: &PgPool, retention: Duration) -> Result<usize, sqlx::Error> {
+    let cutoff = TimeDelta::from_std(retention)
+        .ok()
+        .and_then(|retention| Utc::now().checked_sub_signed(retention))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let mut purged = 0;
+
+    loop {
+        let mut tx = pool.begin().await?;
+
+        let todos = sqlx::query_as::<_, Todo>(&format!(
+            "DELETE FROM todos WHERE id IN ( \
+                 SELECT id FROM todos WHERE deleted_at < $1 \
+                 ORDER BY deleted_at LIMIT $2 FOR UPDATE SKIP LOCKED \
+             ) RETURNING {TODO_COLUMNS}"
+        ))
+        .bind(cutoff)
+        .bind(PURGE_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for todo in &todos {
+            record_change(&mut tx, &Actor::default(), TodoAction::Purged, todo, None).await?;
+        }
+
+        tx.commit().await?;
+
+        purged += todos.len();
+        if (todos.len() as i64) < PURGE_BATCH_SIZE {
+            return Ok(purged);
+        }
+    }
+}
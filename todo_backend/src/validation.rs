@@ -0,0 +1,353 @@
+//! Rules for todo content and tags, shared by every handler that writes them.
+//!
+//! Content is NFC-normalized and its whitespace collapsed before it is
+//! checked, and its length is counted in Unicode scalar values, as
+//! Postgres' `length()` does for the `todos_content_check` constraint.
+//! Tags are normalized the same way and lowercased.
+
+use std::fmt;
+
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
+
+use crate::error::Error;
+
+/// The limit enforced by the `todos` table's check constraint.
+pub const MAX_CONTENT_CHARS: usize = 140;
+
+/// The limit enforced by the `tags` table's check constraint.
+pub const MAX_TAG_CHARS: usize = 32;
+
+pub const MAX_TAGS_PER_TODO: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentPolicy {
+    pub min_chars: usize,
+    pub max_chars: usize,
+    /// Whole words rejected regardless of case.
+    pub banned_words: Vec<String>,
+    /// Hosts links may point to, subdomains included; `None` allows any.
+    pub allowed_url_hosts: Option<Vec<String>>,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        Self {
+            min_chars: 1,
+            max_chars: MAX_CONTENT_CHARS,
+            banned_words: Vec::new(),
+            allowed_url_hosts: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentError {
+    Empty,
+    TooShort { min: usize },
+    TooLong { max: usize, length: usize },
+    ControlCharacter(char),
+    BannedWord,
+    UrlNotAllowed(String),
+}
+
+impl fmt::Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentError::Empty => write!(f, "Todo content cannot be empty"),
+            ContentError::TooShort { min } => write!(f, "Todo content must be at least {} characters", min),
+            ContentError::TooLong { max, length } => {
+                write!(f, "Todo content must be {} characters or less (got {})", max, length)
+            }
+            ContentError::ControlCharacter(c) => {
+                write!(f, "Todo content must not contain control characters (found U+{:04X})", *c as u32)
+            }
+            ContentError::BannedWord => write!(f, "Todo content contains a banned word"),
+            ContentError::UrlNotAllowed(host) => write!(f, "Links to {} are not allowed", host),
+        }
+    }
+}
+
+impl From<ContentError> for Error {
+    fn from(e: ContentError) -> Self {
+        Error::Validation(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagError {
+    Empty,
+    TooLong { tag: String },
+    /// Control characters, and commas, which separate tags in forms.
+    InvalidCharacter(char),
+    TooMany { max: usize },
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::Empty => write!(f, "Tags cannot be empty"),
+            TagError::TooLong { tag } => {
+                write!(f, "Tag \"{}\" must be {} characters or less", tag, MAX_TAG_CHARS)
+            }
+            TagError::InvalidCharacter(c) => write!(f, "Tags must not contain U+{:04X}", *c as u32),
+            TagError::TooMany { max } => write!(f, "A todo can have at most {} tags", max),
+        }
+    }
+}
+
+impl From<TagError> for Error {
+    fn from(e: TagError) -> Self {
+        Error::Validation(e.to_string())
+    }
+}
+
+impl ContentPolicy {
+    /// Reads `TODO_CONTENT_MIN_CHARS`, `TODO_CONTENT_MAX_CHARS`,
+    /// `TODO_BANNED_WORDS` and `TODO_ALLOWED_URL_HOSTS` (both
+    /// comma-separated), falling back to the defaults.
+    pub fn from_env() -> Result<Self, String> {
+        let mut policy = Self::default();
+
+        let number = |name: &str| -> Result<Option<usize>, String> {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.parse().map_err(|_| format!("{} must be a positive integer", name)))
+                .transpose()
+        };
+        let list = |name: &str| {
+            std::env::var(name).ok().map(|value| {
+                value
+                    .split(',')
+                    .map(|item| item.trim().to_lowercase())
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        if let Some(min) = number("TODO_CONTENT_MIN_CHARS")? {
+            policy.min_chars = min;
+        }
+        if let Some(max) = number("TODO_CONTENT_MAX_CHARS")? {
+            policy.max_chars = max;
+        }
+        if policy.min_chars == 0 || policy.min_chars > policy.max_chars || policy.max_chars > MAX_CONTENT_CHARS {
+            return Err(format!(
+                "content limits must satisfy 1 <= TODO_CONTENT_MIN_CHARS <= TODO_CONTENT_MAX_CHARS <= {}",
+                MAX_CONTENT_CHARS
+            ));
+        }
+
+        if let Some(words) = list("TODO_BANNED_WORDS") {
+            policy.banned_words = words;
+        }
+        policy.allowed_url_hosts = list("TODO_ALLOWED_URL_HOSTS");
+
+        Ok(policy)
+    }
+
+    /// Normalizes `raw` and checks it against the policy, returning the
+    /// content to store.
+    pub fn check(&self, raw: &str) -> Result<String, ContentError> {
+        if let Some(c) = raw.chars().find(|c| is_forbidden(*c)) {
+            return Err(ContentError::ControlCharacter(c));
+        }
+
+        let content = normalize(raw);
+        let length = content.chars().count();
+
+        if length == 0 {
+            return Err(ContentError::Empty);
+        }
+        if length < self.min_chars {
+            return Err(ContentError::TooShort { min: self.min_chars });
+        }
+        if length > self.max_chars {
+            return Err(ContentError::TooLong {
+                max: self.max_chars,
+                length,
+            });
+        }
+
+        if !self.banned_words.is_empty() {
+            let lowercase = content.to_lowercase();
+            let banned = lowercase
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| self.banned_words.iter().any(|banned| banned == word));
+            if banned {
+                return Err(ContentError::BannedWord);
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_url_hosts {
+            for host in url_hosts(&content) {
+                let permitted = allowed
+                    .iter()
+                    .any(|allowed| host == *allowed || host.ends_with(&format!(".{}", allowed)));
+                if !permitted {
+                    return Err(ContentError::UrlNotAllowed(host));
+                }
+            }
+        }
+
+        Ok(content)
+    }
+}
+
+/// Normalizes and lowercases a single tag.
+pub fn check_tag(raw: &str) -> Result<String, TagError> {
+    if let Some(c) = raw.chars().find(|c| is_forbidden(*c) || *c == ',') {
+        return Err(TagError::InvalidCharacter(c));
+    }
+
+    let tag = tag_key(raw);
+
+    if tag.is_empty() {
+        return Err(TagError::Empty);
+    }
+    if tag.chars().count() > MAX_TAG_CHARS {
+        return Err(TagError::TooLong { tag });
+    }
+
+    Ok(tag)
+}
+
+/// Checks every tag, returning them deduplicated and sorted by code point,
+/// the order the database lists them in.
+pub fn check_tags(raw: &[String]) -> Result<Vec<String>, TagError> {
+    let mut tags = raw.iter().map(|tag| check_tag(tag)).collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+
+    if tags.len() > MAX_TAGS_PER_TODO {
+        return Err(TagError::TooMany { max: MAX_TAGS_PER_TODO });
+    }
+
+    Ok(tags)
+}
+
+/// The form a tag is stored and looked up in.
+pub fn tag_key(raw: &str) -> String {
+    normalize(raw).to_lowercase()
+}
+
+/// NFC-normalizes `raw`, turns every run of whitespace into a single space
+/// and trims the ends.
+pub fn normalize(raw: &str) -> String {
+    let nfc: String = raw.nfc().collect();
+    nfc.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Control characters other than whitespace, and the bidirectional overrides
+/// that can make text display differently from how it reads.
+fn is_forbidden(c: char) -> bool {
+    (c.is_control() && !c.is_whitespace()) || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Lowercased hosts of the `http(s)://` and `www.` links in `content`.
+fn url_hosts(content: &str) -> Vec<String> {
+    content
+        .split_whitespace()
+        .filter_map(|word| {
+            let lowercase = word.to_lowercase();
+            let candidate = if lowercase.starts_with("http://") || lowercase.starts_with("https://") {
+                word.to_string()
+            } else if lowercase.starts_with("www.") {
+                format!("http://{}", word)
+            } else {
+                return None;
+            };
+            Url::parse(&candidate).ok()?.host_str().map(str::to_lowercase)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        let policy = ContentPolicy::default();
+
+        let emoji = "🎉".repeat(50);
+        assert_eq!(policy.check(&emoji), Ok(emoji.clone()));
+
+        let japanese = "買い物リストを作る".repeat(15);
+        assert_eq!(japanese.chars().count(), 135);
+        assert!(policy.check(&japanese).is_ok());
+
+        let arabic = "مرحبا ".repeat(24);
+        assert_eq!(
+            policy.check(&arabic),
+            Err(ContentError::TooLong { max: 140, length: 143 })
+        );
+    }
+
+    #[test]
+    fn normalizes_to_nfc_and_collapses_whitespace() {
+        let policy = ContentPolicy::default();
+
+        // "é" as "e" followed by a combining acute accent.
+        let decomposed = "  Cafe\u{301}\t au\u{a0}lait\n ";
+        assert_eq!(policy.check(decomposed), Ok("Café au lait".to_string()));
+        assert_eq!(normalize("Ångström"), "Ångström");
+        assert_eq!(policy.check(" \u{3000} "), Err(ContentError::Empty));
+    }
+
+    #[test]
+    fn rejects_control_and_bidi_override_characters() {
+        let policy = ContentPolicy::default();
+
+        assert_eq!(policy.check("Pay\u{0}rent"), Err(ContentError::ControlCharacter('\u{0}')));
+        assert_eq!(
+            policy.check("invoice\u{202E}fdp.exe"),
+            Err(ContentError::ControlCharacter('\u{202E}'))
+        );
+        // Joiners are part of emoji sequences and stay allowed.
+        assert!(policy.check("👩\u{200D}💻 Ship it").is_ok());
+    }
+
+    #[test]
+    fn tags_are_normalized_deduplicated_and_sorted() {
+        let tags = ["  Work ", "home", "WORK", "Cafe\u{301}"].map(String::from);
+        assert_eq!(
+            check_tags(&tags),
+            Ok(vec!["café".to_string(), "home".to_string(), "work".to_string()])
+        );
+
+        assert_eq!(check_tag("Straße"), Ok("straße".to_string()));
+        assert_eq!(check_tag(" "), Err(TagError::Empty));
+        assert_eq!(check_tag("a,b"), Err(TagError::InvalidCharacter(',')));
+        assert!(check_tag(&"タ".repeat(MAX_TAG_CHARS)).is_ok());
+        assert!(matches!(check_tag(&"タ".repeat(MAX_TAG_CHARS + 1)), Err(TagError::TooLong { .. })));
+
+        let many: Vec<String> = (0..=MAX_TAGS_PER_TODO).map(|i| format!("tag{}", i)).collect();
+        assert_eq!(check_tags(&many), Err(TagError::TooMany { max: MAX_TAGS_PER_TODO }));
+    }
+
+    #[test]
+    fn applies_limits_banned_words_and_url_allowlist() {
+        let policy = ContentPolicy {
+            min_chars: 3,
+            max_chars: 40,
+            banned_words: vec!["spam".to_string(), "сканворд".to_string()],
+            allowed_url_hosts: Some(vec!["example.com".to_string()]),
+        };
+
+        assert_eq!(policy.check("ok"), Err(ContentError::TooShort { min: 3 }));
+        assert_eq!(policy.check("Buy SPAM now"), Err(ContentError::BannedWord));
+        assert_eq!(policy.check("Решить СКАНВОРД"), Err(ContentError::BannedWord));
+        assert!(policy.check("Read about spamming filters").is_ok());
+        assert!(policy.check("See https://docs.example.com/todo").is_ok());
+        assert!(policy.check("See www.Example.com").is_ok());
+        assert_eq!(
+            policy.check("See https://evil.test/x"),
+            Err(ContentError::UrlNotAllowed("evil.test".to_string()))
+        );
+        assert_eq!(
+            policy.check("See https://example.com.evil.test"),
+            Err(ContentError::UrlNotAllowed("example.com.evil.test".to_string()))
+        );
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    let schema = todo_events::json_schema();
+    println!("{}", serde_json::to_string_pretty(&schema).expect("JSON Schema is serializable"));
+}
@@ -0,0 +1,44 @@
+//! JetStream streams carrying `todo.events`, declared identically by every
+//! service so whichever starts first creates them.
+
+use std::time::Duration;
+
+use async_nats::jetstream::context::CreateStreamError;
+use async_nats::jetstream::stream::{self, Stream};
+use async_nats::jetstream::Context;
+
+use crate::{DEAD_LETTER_STREAM, DEAD_LETTER_SUBJECT, STREAM, SUBJECT};
+
+/// How long publishes are deduplicated by `Nats-Msg-Id` (the event id).
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEAD_LETTER_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub fn stream_config() -> stream::Config {
+    stream::Config {
+        name: STREAM.to_string(),
+        subjects: vec![SUBJECT.to_string()],
+        storage: stream::StorageType::File,
+        max_age: MAX_AGE,
+        duplicate_window: DUPLICATE_WINDOW,
+        ..Default::default()
+    }
+}
+
+pub fn dead_letter_stream_config() -> stream::Config {
+    stream::Config {
+        name: DEAD_LETTER_STREAM.to_string(),
+        subjects: vec![DEAD_LETTER_SUBJECT.to_string()],
+        storage: stream::StorageType::File,
+        max_age: DEAD_LETTER_MAX_AGE,
+        ..Default::default()
+    }
+}
+
+pub async fn get_or_create_stream(jetstream: &Context) -> Result<Stream, CreateStreamError> {
+    jetstream.get_or_create_stream(stream_config()).await
+}
+
+pub async fn get_or_create_dead_letter_stream(jetstream: &Context) -> Result<Stream, CreateStreamError> {
+    jetstream.get_or_create_stream(dead_letter_stream_config()).await
+}
@@ -0,0 +1,292 @@
+//! The `todo.events` message contract shared by todo_backend (producer) and
+//! broadcaster (consumer).
+//!
+//! Every message is a JSON-encoded [`TodoEvent`]. Additive changes (new
+//! optional fields, new actions) keep [`SCHEMA_VERSION`]; anything that would
+//! break an existing consumer bumps it. The generated JSON Schema lives in
+//! `schema/todo-event.schema.json` and is regenerated with
+//! `cargo run --example generate_schema > schema/todo-event.schema.json`.
+//!
+//! Messages are stored in the [`STREAM`] JetStream stream; the `jetstream`
+//! feature provides its configuration, and the `nats` feature the connection
+//! settings the services share.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[cfg(feature = "jetstream")]
+pub mod jetstream;
+#[cfg(feature = "nats")]
+pub mod nats;
+
+pub const SUBJECT: &str = "todo.events";
+
+pub const STREAM: &str = "TODO_EVENTS";
+
+/// Where consumers park messages they repeatedly failed to handle. The
+/// original payload is kept as-is, with the reason in message headers.
+pub const DEAD_LETTER_SUBJECT: &str = "todo.events.dead_letter";
+
+pub const DEAD_LETTER_STREAM: &str = "TODO_EVENTS_DEAD_LETTER";
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What happened to the todo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoAction {
+    Created,
+    Updated,
+    Reopened,
+    /// Moved to the trash, from where it can still be restored.
+    Deleted,
+    /// Taken back out of the trash.
+    Restored,
+    /// Removed from the trash for good.
+    Purged,
+    /// Any action this version of the contract does not know about. Never
+    /// produced; lets older consumers keep working when actions are added.
+    #[serde(other)]
+    #[schemars(skip)]
+    Unknown,
+}
+
+impl TodoAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Reopened => "reopened",
+            Self::Deleted => "deleted",
+            Self::Restored => "restored",
+            Self::Purged => "purged",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for TodoAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How important a todo is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            other => Err(format!("unknown priority '{}'", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for Priority {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// State of a todo at the time of the event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TodoSnapshot {
+    pub id: i32,
+    pub content: String,
+    pub done: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub version: i32,
+    /// Missing from events produced before priorities existed.
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<DateTime<Utc>>,
+    /// Sorted and lowercase.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The list the todo belongs to; missing from events produced before
+    /// lists existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_id: Option<i32>,
+    /// When the todo was moved to the trash, if it is there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// Envelope of a single `todo.events` message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TodoEvent {
+    /// Unique per event and stable across redeliveries; use it to deduplicate.
+    pub event_id: Uuid,
+    pub schema_version: u32,
+    pub occurred_at: DateTime<Utc>,
+    /// Name of the producing service, e.g. `todo-backend`.
+    pub source: String,
+    pub action: TodoAction,
+    /// The todo after the change, or its last state for `purged`.
+    pub todo: TodoSnapshot,
+    /// The todo before the change, for `updated`, `reopened`, `deleted` and
+    /// `restored`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<TodoSnapshot>,
+}
+
+impl TodoEvent {
+    pub fn new(
+        source: impl Into<String>,
+        action: TodoAction,
+        todo: TodoSnapshot,
+        previous: Option<TodoSnapshot>,
+    ) -> Self {
+        Self {
+            event_id: Uuid::new_v4(),
+            schema_version: SCHEMA_VERSION,
+            occurred_at: Utc::now(),
+            source: source.into(),
+            action,
+            todo,
+            previous,
+        }
+    }
+
+    /// Whether this consumer understands the event's schema version.
+    pub fn is_supported(&self) -> bool {
+        self.schema_version == SCHEMA_VERSION
+    }
+}
+
+/// JSON Schema describing a [`TodoEvent`] message.
+pub fn json_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(TodoEvent)).expect("JSON Schema is serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn snapshot(done: bool, version: i32) -> TodoSnapshot {
+        let created_at = Utc.with_ymd_and_hms(2026, 10, 18, 9, 0, 0).unwrap();
+        TodoSnapshot {
+            id: 7,
+            content: "Read the docs".to_string(),
+            done,
+            created_at,
+            updated_at: created_at,
+            completed_at: done.then_some(created_at),
+            version,
+            priority: Priority::High,
+            due_at: Some(created_at),
+            tags: vec!["docs".to_string()],
+            list_id: Some(1),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn event_round_trips_through_json() {
+        let event = TodoEvent::new(
+            "todo-backend",
+            TodoAction::Reopened,
+            snapshot(false, 3),
+            Some(snapshot(true, 2)),
+        );
+
+        let json = serde_json::to_string(&event).unwrap();
+        let decoded: TodoEvent = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded, event);
+        assert!(decoded.is_supported());
+    }
+
+    #[test]
+    fn previous_is_omitted_when_absent() {
+        let event = TodoEvent::new("todo-backend", TodoAction::Created, snapshot(false, 1), None);
+
+        let value = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(value["action"], "created");
+        assert!(value.get("previous").is_none());
+    }
+
+    #[test]
+    fn unknown_actions_decode_as_unknown() {
+        let mut value = serde_json::to_value(TodoEvent::new(
+            "todo-backend",
+            TodoAction::Created,
+            snapshot(false, 1),
+            None,
+        ))
+        .unwrap();
+        value["action"] = "archived".into();
+
+        let decoded: TodoEvent = serde_json::from_value(value).unwrap();
+
+        assert_eq!(decoded.action, TodoAction::Unknown);
+    }
+
+    #[test]
+    fn snapshots_from_before_priorities_still_decode() {
+        let mut value = serde_json::to_value(snapshot(false, 1)).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("priority");
+        fields.remove("due_at");
+        fields.remove("tags");
+        fields.remove("list_id");
+
+        let decoded: TodoSnapshot = serde_json::from_value(value).unwrap();
+
+        assert_eq!(decoded.priority, Priority::Normal);
+        assert_eq!(decoded.due_at, None);
+        assert!(decoded.tags.is_empty());
+        assert_eq!(decoded.list_id, None);
+    }
+
+    #[test]
+    fn committed_schema_is_up_to_date() {
+        let committed: serde_json::Value =
+            serde_json::from_str(include_str!("../schema/todo-event.schema.json")).unwrap();
+
+        assert_eq!(
+            committed,
+            json_schema(),
+            "schema/todo-event.schema.json is stale, regenerate it with \
+             `cargo run --example generate_schema > schema/todo-event.schema.json`"
+        );
+    }
+}
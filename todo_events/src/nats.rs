@@ -0,0 +1,469 @@
+//! NATS connection settings shared by the services that use `todo.events`,
+//! read from the environment:
+//!
+//! - `NATS_URL`: server URL; `tls://` implies TLS.
+//! - `NATS_CONNECT_MAX_ATTEMPTS`: attempts at the first connection, retried
+//!   with backoff; unset or `0` keeps trying.
+//! - `NATS_RECONNECT_BUFFER_SIZE`: commands buffered while disconnected.
+//! - `NATS_TOKEN`, `NATS_USER` with `NATS_PASSWORD`, `NATS_NKEY` (seed) or
+//!   `NATS_CREDS` (path to a `.creds` file): at most one form of auth.
+//! - `NATS_TLS_REQUIRED`, `NATS_TLS_CA_FILE`, and `NATS_TLS_CERT_FILE` with
+//!   `NATS_TLS_KEY_FILE` for client certificates.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_nats::{Client, ConnectOptions, Event};
+use tokio::sync::Notify;
+
+const DEFAULT_URL: &str = "nats://my-nats:4222";
+/// async-nats' own default.
+const DEFAULT_RECONNECT_BUFFER_SIZE: usize = 2048;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The environment describes an invalid configuration.
+    Config(String),
+    /// The credentials file could not be read.
+    Credentials(std::io::Error),
+    Connect(async_nats::ConnectError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Config(reason) => write!(f, "invalid NATS configuration: {}", reason),
+            Error::Credentials(e) => write!(f, "failed to read NATS credentials: {}", e),
+            Error::Connect(e) => write!(f, "failed to connect to NATS: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectConfig {
+    pub url: String,
+    /// `None` retries the first connection forever.
+    pub max_connect_attempts: Option<u32>,
+    pub reconnect_buffer_size: usize,
+    pub auth: Auth,
+    pub tls: Tls,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Auth {
+    #[default]
+    None,
+    Token(String),
+    UserPassword { user: String, password: String },
+    NKey(String),
+    CredentialsFile(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tls {
+    pub required: bool,
+    pub ca_file: Option<PathBuf>,
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl ConnectConfig {
+    pub fn from_env() -> Result<Self, Error> {
+        Self::from_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let number = |name: &str| -> Result<Option<usize>, Error> {
+            var(name)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| Error::Config(format!("{} must be a non-negative integer", name)))
+                })
+                .transpose()
+        };
+
+        let max_connect_attempts = number("NATS_CONNECT_MAX_ATTEMPTS")?
+            .filter(|attempts| *attempts > 0)
+            .map(|attempts| attempts.min(u32::MAX as usize) as u32);
+
+        let reconnect_buffer_size = match number("NATS_RECONNECT_BUFFER_SIZE")? {
+            Some(0) => return Err(Error::Config("NATS_RECONNECT_BUFFER_SIZE must be at least 1".to_string())),
+            Some(size) => size,
+            None => DEFAULT_RECONNECT_BUFFER_SIZE,
+        };
+
+        let mut auths = Vec::new();
+        if let Some(token) = var("NATS_TOKEN") {
+            auths.push(Auth::Token(token));
+        }
+        match (var("NATS_USER"), var("NATS_PASSWORD")) {
+            (Some(user), Some(password)) => auths.push(Auth::UserPassword { user, password }),
+            (None, None) => {}
+            _ => return Err(Error::Config("NATS_USER and NATS_PASSWORD must be set together".to_string())),
+        }
+        if let Some(seed) = var("NATS_NKEY") {
+            auths.push(Auth::NKey(seed));
+        }
+        if let Some(path) = var("NATS_CREDS") {
+            auths.push(Auth::CredentialsFile(path.into()));
+        }
+        if auths.len() > 1 {
+            return Err(Error::Config(
+                "set only one of NATS_TOKEN, NATS_USER, NATS_NKEY and NATS_CREDS".to_string(),
+            ));
+        }
+
+        let client_cert = match (var("NATS_TLS_CERT_FILE"), var("NATS_TLS_KEY_FILE")) {
+            (Some(cert), Some(key)) => Some((cert.into(), key.into())),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Config(
+                    "NATS_TLS_CERT_FILE and NATS_TLS_KEY_FILE must be set together".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self {
+            url: var("NATS_URL").unwrap_or_else(|| DEFAULT_URL.to_string()),
+            max_connect_attempts,
+            reconnect_buffer_size,
+            auth: auths.pop().unwrap_or_default(),
+            tls: Tls {
+                required: var("NATS_TLS_REQUIRED").is_some_and(|value| value == "true"),
+                ca_file: var("NATS_TLS_CA_FILE").map(PathBuf::from),
+                client_cert,
+            },
+        })
+    }
+
+    async fn options(&self) -> Result<ConnectOptions, Error> {
+        let mut options = ConnectOptions::new()
+            .client_capacity(self.reconnect_buffer_size)
+            .event_callback(|event| async move { log_event(event) });
+
+        options = match &self.auth {
+            Auth::None => options,
+            Auth::Token(token) => options.token(token.clone()),
+            Auth::UserPassword { user, password } => options.user_and_password(user.clone(), password.clone()),
+            Auth::NKey(seed) => options.nkey(seed.clone()),
+            Auth::CredentialsFile(path) => options.credentials_file(path).await.map_err(Error::Credentials)?,
+        };
+
+        if self.tls.required {
+            options = options.require_tls(true);
+        }
+        if let Some(ca_file) = &self.tls.ca_file {
+            options = options.add_root_certificates(ca_file.clone());
+        }
+        if let Some((cert, key)) = &self.tls.client_cert {
+            options = options.add_client_certificate(cert.clone(), key.clone());
+        }
+
+        Ok(options)
+    }
+}
+
+/// Connects, retrying the first connection with exponential backoff. Once
+/// connected, the client reconnects on its own and every state change is
+/// logged.
+pub async fn connect(config: &ConnectConfig) -> Result<Client, Error> {
+    let mut attempt = 1;
+
+    loop {
+        tracing::info!("Connecting to NATS at {}", config.url);
+
+        match config.options().await?.connect(&config.url).await {
+            Ok(client) => {
+                tracing::info!("Connected to NATS successfully");
+                return Ok(client);
+            }
+            Err(e) if config.max_connect_attempts.is_some_and(|max| attempt >= max) => {
+                return Err(Error::Connect(e));
+            }
+            Err(e) => {
+                let delay = retry_delay(attempt);
+                tracing::warn!(
+                    "Failed to connect to NATS: {}. Retrying in {}ms...",
+                    e,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Returns a client straight away and connects in the background, so a
+/// service can serve requests that do not need NATS while it is down.
+/// Requests made before the first connection time out.
+///
+/// `max_connect_attempts` only bounds the first connection, which [`Status`]
+/// reports; afterwards the client reconnects for as long as it lives, as
+/// async-nats' own limit would also cap every later outage.
+pub async fn connect_in_background(config: &ConnectConfig) -> Result<(Client, Status), Error> {
+    tracing::info!("Connecting to NATS at {} in the background", config.url);
+
+    let status = Status::default();
+    let max_attempts = config.max_connect_attempts;
+
+    let options = config
+        .options()
+        .await?
+        .event_callback({
+            let status = status.clone();
+            move |event| {
+                let status = status.clone();
+                async move {
+                    match event {
+                        Event::Connected => status.set_connected(),
+                        Event::Closed => status.inner.closed.store(true, Ordering::SeqCst),
+                        _ => {}
+                    }
+                    log_event(event)
+                }
+            }
+        })
+        .reconnect_delay_callback({
+            let status = status.clone();
+            let last_attempt = AtomicUsize::new(0);
+            move |attempts| {
+                // `attempts` starts over after every successful connection,
+                // which may be seen here before its `Connected` event.
+                if attempts <= last_attempt.swap(attempts, Ordering::SeqCst) {
+                    status.set_connected();
+                }
+                let connected = status.inner.connected.load(Ordering::SeqCst);
+                let attempts = attempts.min(u32::MAX as usize) as u32;
+                if !connected && max_attempts.is_some_and(|max| attempts > max) {
+                    status.give_up();
+                }
+                match attempts {
+                    0 | 1 => Duration::ZERO,
+                    attempts => retry_delay(attempts - 1),
+                }
+            }
+        })
+        .retry_on_initial_connect();
+
+    let client = options.connect(&config.url).await.map_err(Error::Connect)?;
+
+    Ok((client, status))
+}
+
+/// What a client from [`connect_in_background`] does not report itself.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    inner: Arc<StatusInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatusInner {
+    connected: AtomicBool,
+    gave_up: AtomicBool,
+    closed: AtomicBool,
+    given_up: Notify,
+    first_connected: Notify,
+}
+
+impl Status {
+    /// Whether the first connection ran out of `max_connect_attempts`.
+    pub fn gave_up(&self) -> bool {
+        self.inner.gave_up.load(Ordering::SeqCst)
+    }
+
+    /// Whether the client has closed and will not reconnect, e.g. after
+    /// draining.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the client has connected for the first time.
+    pub async fn connected(&self) {
+        let connected = self.inner.first_connected.notified();
+        if !self.inner.connected.load(Ordering::SeqCst) {
+            connected.await;
+        }
+    }
+
+    /// Resolves once the first connection has run out of attempts, so the
+    /// service can exit; never when they are unbounded.
+    pub async fn given_up(&self) {
+        let given_up = self.inner.given_up.notified();
+        if !self.gave_up() {
+            given_up.await;
+        }
+    }
+
+    fn set_connected(&self) {
+        if !self.inner.connected.swap(true, Ordering::SeqCst) {
+            self.inner.first_connected.notify_waiters();
+        }
+    }
+
+    fn give_up(&self) {
+        if !self.inner.gave_up.swap(true, Ordering::SeqCst) {
+            self.inner.given_up.notify_waiters();
+        }
+    }
+}
+
+fn log_event(event: Event) {
+    match event {
+        Event::Connected => tracing::info!("NATS connection established"),
+        Event::Disconnected => tracing::warn!("NATS connection lost, reconnecting"),
+        Event::LameDuckMode => tracing::warn!("NATS server entered lame duck mode"),
+        Event::Draining => tracing::info!("NATS connection draining"),
+        Event::Closed => tracing::info!("NATS connection closed"),
+        Event::SlowConsumer(sid) => tracing::warn!("NATS subscription {} is a slow consumer", sid),
+        Event::ServerError(e) => tracing::error!("NATS server error: {}", e),
+        Event::ClientError(e) => tracing::error!("NATS client error: {}", e),
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    INITIAL_RETRY_DELAY
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Result<ConnectConfig, Error> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        ConnectConfig::from_vars(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn defaults_retry_forever_without_auth() {
+        let config = config(&[]).unwrap();
+
+        assert_eq!(config.url, DEFAULT_URL);
+        assert_eq!(config.max_connect_attempts, None);
+        assert_eq!(config.reconnect_buffer_size, DEFAULT_RECONNECT_BUFFER_SIZE);
+        assert_eq!(config.auth, Auth::None);
+        assert_eq!(config.tls, Tls::default());
+    }
+
+    #[test]
+    fn reads_auth_and_tls() {
+        let config = config(&[
+            ("NATS_URL", "tls://nats.example.com:4222"),
+            ("NATS_CONNECT_MAX_ATTEMPTS", "5"),
+            ("NATS_RECONNECT_BUFFER_SIZE", "4096"),
+            ("NATS_CREDS", "/etc/nats/todo.creds"),
+            ("NATS_TLS_CA_FILE", "/etc/nats/ca.pem"),
+            ("NATS_TLS_CERT_FILE", "/etc/nats/cert.pem"),
+            ("NATS_TLS_KEY_FILE", "/etc/nats/key.pem"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.max_connect_attempts, Some(5));
+        assert_eq!(config.reconnect_buffer_size, 4096);
+        assert_eq!(config.auth, Auth::CredentialsFile("/etc/nats/todo.creds".into()));
+        assert_eq!(config.tls.ca_file, Some("/etc/nats/ca.pem".into()));
+        assert_eq!(
+            config.tls.client_cert,
+            Some(("/etc/nats/cert.pem".into(), "/etc/nats/key.pem".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_settings() {
+        assert!(matches!(config(&[("NATS_TOKEN", "t"), ("NATS_NKEY", "SU")]), Err(Error::Config(_))));
+        assert!(matches!(config(&[("NATS_USER", "todo")]), Err(Error::Config(_))));
+        assert!(matches!(config(&[("NATS_TLS_CERT_FILE", "cert.pem")]), Err(Error::Config(_))));
+        assert!(matches!(config(&[("NATS_RECONNECT_BUFFER_SIZE", "0")]), Err(Error::Config(_))));
+        assert!(matches!(config(&[("NATS_CONNECT_MAX_ATTEMPTS", "-1")]), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(3), Duration::from_secs(2));
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+    }
+
+    /// A URL nothing listens on.
+    async fn unused_url() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("nats://{}", listener.local_addr().unwrap())
+    }
+
+    fn bounded(url: String, max_connect_attempts: u32) -> ConnectConfig {
+        ConnectConfig {
+            url,
+            max_connect_attempts: Some(max_connect_attempts),
+            ..config(&[]).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn background_connections_give_up_after_max_attempts() {
+        let (_client, status) = connect_in_background(&bounded(unused_url().await, 2)).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), status.given_up())
+            .await
+            .expect("the first connection should give up");
+        assert!(status.gave_up());
+    }
+
+    /// Accepts one connection and closes it shortly after the handshake.
+    async fn serve_once(listener: tokio::net::TcpListener) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        socket
+            .write_all(b"INFO {\"server_id\":\"test\",\"max_payload\":1048576,\"proto\":1}\r\n")
+            .await
+            .unwrap();
+        let mut buffer = [0; 1024];
+        let _ = socket.read(&mut buffer).await;
+        socket.write_all(b"PONG\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    async fn wait_until_connected(client: &Client, within: Duration) {
+        tokio::time::timeout(within, async {
+            while client.connection_state() != async_nats::connection::State::Connected {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the client should connect");
+    }
+
+    #[tokio::test]
+    async fn reconnects_are_not_limited_by_max_attempts() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_once(listener));
+
+        let (client, status) = connect_in_background(&bounded(format!("nats://{}", address), 1))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), status.connected())
+            .await
+            .expect("the client should connect");
+        server.await.unwrap();
+
+        // Long enough for several failed reconnects.
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(!status.gave_up());
+        assert!(!status.is_closed());
+
+        let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+        tokio::spawn(serve_once(listener));
+        wait_until_connected(&client, Duration::from_secs(15)).await;
+    }
+}